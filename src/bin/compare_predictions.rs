@@ -0,0 +1,61 @@
+use serenade::io::read_predictions;
+use serenade::metrics::evaluation_reporter::EvaluationProtocol;
+use serenade::metrics::significance::{comparison_table, confidence_interval_table, EventScores};
+
+fn main() {
+    // This tool compares two prediction files of the same test events, like the ones written by the
+    // evaluator with `predictions_path` for two configurations, with a paired bootstrap.
+    // Usage: compare_predictions <predictions a> <predictions b> [cutoff]
+    //        [--protocol <remaining_items|next_item|next_items:n>]
+    let mut positional_args = Vec::new();
+    let mut protocol = EvaluationProtocol::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--protocol" => {
+                let value = args.next().unwrap_or_else(|| panic!("{} needs a value", arg));
+                protocol = value.parse().unwrap_or_else(|err| panic!("{}", err));
+            }
+            flag if flag.starts_with("--") => panic!("Unknown option: {}", flag),
+            _ => positional_args.push(arg),
+        }
+    }
+    let predictions_file_a = positional_args.first().cloned().unwrap_or_default();
+    let predictions_file_b = positional_args.get(1).cloned().unwrap_or_default();
+    let length: usize = positional_args
+        .get(2)
+        .map(|length| length.parse().expect("The cutoff must be a number"))
+        .unwrap_or(20);
+    let qty_resamples = 1000;
+    let confidence_level = 0.95;
+
+    let read_scores = |predictions_file: &str| {
+        let predictions = read_predictions(predictions_file)
+            .unwrap_or_else(|err| panic!("Cannot read the predictions from {}: {}", predictions_file, err));
+        let mut scores = EventScores::new(length);
+        for prediction in predictions.iter() {
+            let key = prediction.key.unwrap_or_else(|| {
+                panic!("Cannot pair the predictions in {} without event keys", predictions_file)
+            });
+            // Scores the same target items as the evaluator with this protocol.
+            scores.add(key, &prediction.recommendations, protocol.target_items(&prediction.next_items));
+        }
+        scores
+    };
+    let scores_a = read_scores(&predictions_file_a);
+    let scores_b = read_scores(&predictions_file_b);
+    let comparison = scores_a
+        .compare(&scores_b, qty_resamples, confidence_level)
+        .unwrap_or_else(|err| panic!("{}", err));
+
+    println!("===============================================================");
+    println!("===            COMPARING PREDICTIONS BY FILE               ====");
+    println!("===============================================================");
+    println!("evaluation protocol: {}", protocol);
+    println!("predictions a: {}", predictions_file_a);
+    println!("{}", confidence_interval_table(&scores_a.confidence_intervals(qty_resamples, confidence_level)));
+    println!("predictions b: {}", predictions_file_b);
+    println!("{}", confidence_interval_table(&scores_b.confidence_intervals(qty_resamples, confidence_level)));
+    println!("Paired comparison a - b over {} test events", scores_a.qty_events());
+    println!("{}", comparison_table(&comparison));
+}
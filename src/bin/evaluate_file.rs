@@ -1,44 +1,43 @@
 use itertools::Itertools;
-use serenade::io::read_training_data;
-use std::fs::File;
-use std::io::{self, BufRead};
-use std::path::Path;
-use serenade::metrics::evaluation_reporter::EvaluationReporter;
+use serenade::io::{read_predictions, read_training_data};
+use serenade::metrics::evaluation_reporter::{parse_cutoffs, EvaluationProtocol, EvaluationReporter};
+use serenade::metrics::report::{EvaluationReport, ReportFormat};
 
 fn main() {
     // This tool can evaluate predictions made by computational models and stored as a file.
     // Its needs access to the training data to determine the metrics 'popularity' and 'coverage'.
-    let training_data_path = std::env::args().nth(1).unwrap_or_default();
-    let predictions_file = std::env::args().nth(2).unwrap_or_default();
+    // Usage: evaluate_file <training data> <predictions file> [--report <path>] [--cutoffs <1,5,10,20>]
+    //        [--protocol <remaining_items|next_item|next_items:n>]
+    let mut positional_args = Vec::new();
+    let mut report_path = None;
+    let mut cutoffs = "1,5,10,20".to_string();
+    let mut protocol = EvaluationProtocol::default().to_string();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value_of = |flag: &str| args.next().unwrap_or_else(|| panic!("{} needs a value", flag));
+        match arg.as_str() {
+            "--report" => report_path = Some(value_of(&arg)),
+            "--cutoffs" => cutoffs = value_of(&arg),
+            "--protocol" => protocol = value_of(&arg),
+            flag if flag.starts_with("--") => panic!("Unknown option: {}", flag),
+            _ => positional_args.push(arg),
+        }
+    }
+    let training_data_path = positional_args.first().cloned().unwrap_or_default();
+    let predictions_file = positional_args.get(1).cloned().unwrap_or_default();
+    if let Some(report_path) = &report_path {
+        ReportFormat::validate_path(report_path).unwrap_or_else(|err| panic!("{}", err));
+    }
 
     let training_df = read_training_data(&*training_data_path);
-    let length = 20;
-    let mut reporter = EvaluationReporter::new(&training_df, length);
+    let cutoffs = parse_cutoffs(&cutoffs).unwrap_or_else(|err| panic!("{}", err));
+    let protocol: EvaluationProtocol = protocol.parse().unwrap_or_else(|err| panic!("{}", err));
+    let mut reporter = EvaluationReporter::with_cutoffs(&training_df, &cutoffs).with_protocol(protocol);
 
-    if let Ok(lines) = read_lines(&*predictions_file) {
-        // Consumes the iterator, returns an (Optional) String
-        for result in lines {
-            if let Ok(line) = result {
-                let recos_w_predictions = line.split(";").collect_vec();
-                let recos = *unsafe { recos_w_predictions.get_unchecked(0) };
-                let recos = recos
-                    .split(",")
-                    .collect_vec()
-                    .into_iter()
-                    .filter(|str| str.len() > 0)
-                    .map(|x| x.clone().parse::<u64>().unwrap())
-                    .collect_vec();
-                let next_items = *unsafe { recos_w_predictions.get_unchecked(1) };
-                let next_items = next_items
-                    .split(",")
-                    .collect_vec()
-                    .into_iter()
-                    .map(|x| x.clone().parse::<u64>().unwrap())
-                    .collect_vec();
-                // recos.iter
-                reporter.add(&recos, &next_items);
-            }
-        }
+    let predictions = read_predictions(&predictions_file)
+        .unwrap_or_else(|err| panic!("Cannot read the predictions from {}: {}", predictions_file, err));
+    for prediction in predictions.iter() {
+        reporter.add(&prediction.recommendations, &prediction.next_items);
     }
 
     println!("===============================================================");
@@ -46,15 +45,19 @@ fn main() {
     println!("===============================================================");
     println!("training data: {}", training_data_path);
     println!("predictions file: {}", predictions_file);
-    println!("{}", reporter.get_name());
-    println!("{}", reporter.result());
+    println!("evaluation protocol: {}", protocol);
+    println!("{}", reporter.result_table());
 
-}
-
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-where
-    P: AsRef<Path>,
-{
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+    if let Some(report_path) = report_path {
+        let mut report = EvaluationReport::new(&reporter);
+        report.add_config("training_data_path", &training_data_path);
+        report.add_config("predictions_file", &predictions_file);
+        report.add_config("cutoffs", cutoffs.iter().join(","));
+        report.add_config("protocol", protocol);
+        report.add_training_data_stats(&training_df);
+        report
+            .write(&report_path)
+            .unwrap_or_else(|err| panic!("Cannot write the report to {}: {}", report_path, err));
+        println!("report written to: {}", report_path);
+    }
 }
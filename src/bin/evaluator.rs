@@ -1,20 +1,46 @@
 use std::path::Path;
-use serenade::{io, vmisknn};
+use std::sync::Arc;
+use serenade::io::{self, Prediction};
 
-use serenade::vmisknn::vmis_index::VMISIndex;
+use serenade::vmisknn::diversity::{Diversifier, DiversitySimilarity};
+use serenade::vmisknn::fallback::{FallbackRecommender, FallbackStrategy};
+use serenade::vmisknn::vmis_index::{NeighborSimilarity, VMISIndex};
 use serenade::config::AppConfig;
-use serenade::metrics::evaluation_reporter::EvaluationReporter;
+use serenade::metrics::breakdown::{latency_by_position, BreakdownReporter};
+use serenade::metrics::evaluation_reporter::{parse_cutoffs, EvaluationProtocol, EvaluationReporter};
+use serenade::metrics::report::{EvaluationReport, ReportFormat, LATENCY_PERCENTILES};
+use serenade::metrics::significance::{comparison_table, confidence_interval_table, EventScores};
+use serenade::popularity::PopularityIndex;
+use serenade::recommender::{create_recommender, recommend_items, Algorithm, RecommendOptions};
 use serenade::stopwatch::Stopwatch;
 
+use itertools::Itertools;
+use rayon::prelude::*;
+
 fn main() {
     let config_path = std::env::args().nth(1).unwrap_or_default();
     let config = AppConfig::new(config_path);
+    let report_path = &config.evaluation.report_path;
+    if !report_path.is_empty() {
+        ReportFormat::validate_path(report_path).unwrap_or_else(|err| panic!("{}", err));
+    }
 
-    let m_most_recent_sessions = config.model.m_most_recent_sessions;
-    let neighborhood_size_k = config.model.neighborhood_size_k;
-    let num_items_to_recommend = config.model.num_items_to_recommend;
+    let cutoffs = if config.evaluation.cutoffs.is_empty() {
+        vec![config.model.num_items_to_recommend]
+    } else {
+        parse_cutoffs(&config.evaluation.cutoffs).unwrap_or_else(|err| panic!("{}", err))
+    };
+    // Enough recommendations for the largest cutoff.
+    let num_items_to_recommend = *cutoffs.iter().max().unwrap();
     let max_items_in_session = config.model.max_items_in_session;
     let enable_business_logic = config.logic.enable_business_logic;
+    let options = RecommendOptions {
+        neighborhood_size_k: config.model.neighborhood_size_k,
+        m_most_recent_sessions: config.model.m_most_recent_sessions,
+        num_items_to_recommend,
+        enable_business_logic,
+        dedup_consecutive_items: config.model.dedup_consecutive_items,
+    };
 
     let training_data_path = Path::new(&config.data.training_data_path);
     let vmis_index = if training_data_path.is_dir() {
@@ -33,58 +59,288 @@ fn main() {
             &config.data.training_data_path
         )
     };
+    let neighbor_similarity: NeighborSimilarity = config
+        .model
+        .neighbor_similarity
+        .parse()
+        .unwrap_or_else(|err| panic!("{}", err));
+    let vmis_index = Arc::new(vmis_index.with_neighbor_similarity(neighbor_similarity));
+
+    let training_df = io::read_training_data(&*config.data.training_data_path);
+
+    // A popularity baseline, evaluated on the same test events to measure the lift of the algorithm.
+    let popularity_index = Arc::new(PopularityIndex::new(&training_df, config.popularity.window_in_secs));
+
+    let algorithm: Algorithm = config
+        .model
+        .algorithm
+        .parse()
+        .unwrap_or_else(|err| panic!("{}", err));
+    println!("algorithm:{}", algorithm);
+    let recommender = create_recommender(
+        algorithm,
+        vmis_index.clone(),
+        popularity_index.clone(),
+        &config.data.training_data_path,
+        &config.model,
+        &config.ensemble,
+    );
+
+    let fallback_strategy: FallbackStrategy = config
+        .logic
+        .fallback_strategy
+        .parse()
+        .unwrap_or_else(|err| panic!("{}", err));
+    let fallback_recommender = FallbackRecommender::new(
+        &vmis_index,
+        fallback_strategy,
+        config.logic.trending_window_in_secs,
+    );
+
+    let diversity_similarity: DiversitySimilarity = config
+        .diversity
+        .similarity
+        .parse()
+        .unwrap_or_else(|err| panic!("{}", err));
+    let diversifier = Diversifier::new(diversity_similarity, config.diversity.lambda);
+    let diversity_lambda = diversifier.lambda();
+    let candidate_options = RecommendOptions {
+        num_items_to_recommend: Diversifier::qty_candidates(num_items_to_recommend, diversity_lambda),
+        ..options.clone()
+    };
 
     let test_data_file = config.hyperparam.test_data_path;
     println!("test_data_file:{}", test_data_file);
 
-    let ordered_test_sessions = io::read_test_data_evolving(&*test_data_file);
-
-    let mut reporter = EvaluationReporter::new(&io::read_training_data(&*config.data.training_data_path), num_items_to_recommend);
+    // Sorted by session id, so the test events are in the same order in every run and can be paired.
+    let ordered_test_sessions = io::read_test_data_evolving(&*test_data_file)
+        .into_iter()
+        .sorted_by_key(|(session_id, _)| *session_id)
+        .collect_vec();
 
-    let mut stopwatch = Stopwatch::new();
+    let protocol: EvaluationProtocol = config
+        .evaluation
+        .protocol
+        .parse()
+        .unwrap_or_else(|err| panic!("{}", err));
+    println!("evaluation protocol:{}", protocol);
+    let predictions_path = &config.evaluation.predictions_path;
+    // The baseline reporter shares the training item statistics of the reporter.
+    let reporter = EvaluationReporter::with_cutoffs(&training_df, &cutoffs).with_protocol(protocol);
+    let empty_evaluation = Evaluation {
+        baseline_reporter: reporter.clone(),
+        reporter,
+        breakdown_reporter: BreakdownReporter::new(num_items_to_recommend).with_protocol(protocol),
+        scores: EventScores::new(num_items_to_recommend),
+        baseline_scores: EventScores::new(num_items_to_recommend),
+        predictions: None,
+    };
+    let empty_evaluation = if predictions_path.is_empty() {
+        empty_evaluation
+    } else {
+        Evaluation {
+            predictions: Some(Vec::new()),
+            ..empty_evaluation
+        }
+    };
 
-    ordered_test_sessions
-        .iter()
-        .for_each(|(_session_id, evolving_session_items)| {
-            for session_state in 1..evolving_session_items.len() {
-                // use last x items of evolving session
-                let start_index = if session_state > max_items_in_session {
-                    session_state - max_items_in_session
-                } else {
-                    0
-                };
-                let session: &[u64] = &evolving_session_items[start_index..session_state];
+    let evaluate_session = |session_id: u32,
+                            evolving_session_items: &[u64],
+                            evaluation: &mut Evaluation,
+                            stopwatch: &mut Option<Stopwatch>| {
+        for session_state in 1..evolving_session_items.len() {
+            // use last x items of evolving session
+            let start_index = session_state.saturating_sub(max_items_in_session);
+            let session: &[u64] = &evolving_session_items[start_index..session_state];
+            if let Some(stopwatch) = stopwatch.as_mut() {
                 stopwatch.start();
-                let recommendations = vmisknn::predict(
+            }
+            let candidates = recommender.recommend(session, &candidate_options);
+            let mut recommended_items: Vec<u64> = diversifier
+                .rerank(&vmis_index, candidates, diversity_lambda, num_items_to_recommend)
+                .iter()
+                .map(|scored| scored.id)
+                .collect();
+            if let Some(stopwatch) = stopwatch.as_mut() {
+                stopwatch.stop(&session_state);
+            }
+            if recommended_items.is_empty() {
+                recommended_items = fallback_recommender.recommend(
                     &vmis_index,
-                    &session,
-                    neighborhood_size_k,
-                    m_most_recent_sessions,
+                    session,
                     num_items_to_recommend,
                     enable_business_logic,
                 );
-                stopwatch.stop(&start_index);
-                let recommended_items = recommendations
-                    .into_sorted_vec()
-                    .iter()
-                    .map(|scored| scored.id)
-                    .collect::<Vec<u64>>();
+            }
 
-                let actual_next_items = Vec::from(&evolving_session_items[session_state..]);
-                reporter.add(&recommended_items, &actual_next_items);
+            let actual_next_items = Vec::from(&evolving_session_items[session_state..]);
+            let target_items = protocol.target_items(&actual_next_items);
+            evaluation.reporter.add(&recommended_items, &actual_next_items);
+            evaluation.breakdown_reporter.add(
+                session_state,
+                evolving_session_items.len(),
+                &recommended_items,
+                &actual_next_items,
+            );
+            let key = (session_id, session_state);
+            evaluation.scores.add(key, &recommended_items, target_items);
+
+            let baseline_items = recommend_items(popularity_index.as_ref(), session, &options);
+            evaluation.baseline_reporter.add(&baseline_items, &actual_next_items);
+            evaluation.baseline_scores.add(key, &baseline_items, target_items);
+
+            if let Some(predictions) = evaluation.predictions.as_mut() {
+                predictions.push(Prediction {
+                    key: Some(key),
+                    recommendations: recommended_items,
+                    next_items: actual_next_items,
+                });
             }
-        });
+        }
+    };
+
+    let (evaluation, stopwatch) = if config.evaluation.measure_latency {
+        // One prediction at a time, so the latencies are not inflated by concurrent predictions.
+        let mut evaluation = empty_evaluation.clone();
+        let mut stopwatch = Some(Stopwatch::new());
+        for (session_id, evolving_session_items) in ordered_test_sessions.iter() {
+            evaluate_session(*session_id, evolving_session_items, &mut evaluation, &mut stopwatch);
+        }
+        (evaluation, stopwatch)
+    } else {
+        // The partial evaluations are merged in the order of the test sessions.
+        let evaluation = ordered_test_sessions
+            .par_iter()
+            .fold(
+                || empty_evaluation.clone(),
+                |mut evaluation, (session_id, evolving_session_items)| {
+                    evaluate_session(*session_id, evolving_session_items, &mut evaluation, &mut None);
+                    evaluation
+                },
+            )
+            .reduce(
+                || empty_evaluation.clone(),
+                |mut evaluation, other| {
+                    evaluation.merge(other);
+                    evaluation
+                },
+            );
+        (evaluation, None)
+    };
+    let Evaluation {
+        reporter,
+        baseline_reporter,
+        breakdown_reporter,
+        scores,
+        baseline_scores,
+        predictions,
+    } = evaluation;
+
     println!("===============================================================");
     println!("===               START EVALUATING TEST FILE               ====");
     println!("===============================================================");
-    println!("{}", reporter.get_name());
-    println!("{}", reporter.result());
-    println!("Qty test evaluations: {}", stopwatch.get_n());
-    println!("Prediction latency");
-    println!("p25 (microseconds): {}", stopwatch.get_percentile_in_micros(25.0));
-    println!("p50 (microseconds): {}", stopwatch.get_percentile_in_micros(50.0));
-    println!("p75 (microseconds): {}", stopwatch.get_percentile_in_micros(75.0));
-    println!("p90 (microseconds): {}", stopwatch.get_percentile_in_micros(90.0));
-    println!("p95 (microseconds): {}", stopwatch.get_percentile_in_micros(95.0));
-    println!("p99.5 (microseconds): {}", stopwatch.get_percentile_in_micros(99.5));
+    println!("{}", reporter.result_table());
+    println!("Baseline: popularity");
+    println!("{}", baseline_reporter.result_table());
+    println!("Qty test evaluations: {}", reporter.qty_evaluations());
+    println!("Breakdown by position in session and session length");
+    println!("{}", breakdown_reporter.result_table());
+    let bootstrap_resamples = config.evaluation.bootstrap_resamples;
+    let confidence_level = config.evaluation.confidence_level;
+    let (confidence_intervals, comparison) = if bootstrap_resamples > 0 {
+        let confidence_intervals = scores.confidence_intervals(bootstrap_resamples, confidence_level);
+        let comparison = scores
+            .compare(&baseline_scores, bootstrap_resamples, confidence_level)
+            .unwrap_or_else(|err| panic!("{}", err));
+        println!("Bootstrap confidence intervals ({} resamples, level {})", bootstrap_resamples, confidence_level);
+        println!("{}", confidence_interval_table(&confidence_intervals));
+        println!("Paired comparison with the baseline");
+        println!("{}", comparison_table(&comparison));
+        (confidence_intervals, comparison)
+    } else {
+        (Vec::new(), Vec::new())
+    };
+    match &stopwatch {
+        Some(stopwatch) => {
+            println!("Prediction latency");
+            for percentile in LATENCY_PERCENTILES {
+                println!("p{} (microseconds): {}", percentile, stopwatch.get_percentile_in_micros(percentile));
+            }
+            println!("Prediction latency by position in session");
+            for latency_bucket in latency_by_position(stopwatch) {
+                let percentiles = latency_bucket
+                    .latency_in_micros
+                    .iter()
+                    .map(|(name, value)| format!("{}:{}", name, value))
+                    .join(",");
+                println!("position {} (microseconds): {}", latency_bucket.bucket, percentiles);
+            }
+        }
+        None => println!("Prediction latency is measured with measure_latency = true in [evaluation]"),
+    }
+
+    if !report_path.is_empty() {
+        let mut report = EvaluationReport::new(&reporter);
+        report.add_config("algorithm", algorithm);
+        report.add_config("training_data_path", &config.data.training_data_path);
+        report.add_config("test_data_path", &test_data_file);
+        report.add_config("m_most_recent_sessions", options.m_most_recent_sessions);
+        report.add_config("neighborhood_size_k", options.neighborhood_size_k);
+        report.add_config("max_items_in_session", max_items_in_session);
+        report.add_config("num_items_to_recommend", num_items_to_recommend);
+        report.add_config("cutoffs", cutoffs.iter().join(","));
+        report.add_config("protocol", protocol);
+        report.add_config("idf_weighting", config.model.idf_weighting);
+        report.add_config("neighbor_similarity", neighbor_similarity);
+        report.add_config("dedup_consecutive_items", options.dedup_consecutive_items);
+        report.add_config("enable_business_logic", enable_business_logic);
+        report.add_config("fallback_strategy", fallback_strategy);
+        report.add_config("diversity_lambda", diversity_lambda);
+        report.add_config("diversity_similarity", diversifier.similarity());
+        report.add_training_data_stats(&training_df);
+        report.dataset.insert("qty_test_sessions".to_string(), ordered_test_sessions.len());
+        report.set_baseline(&baseline_reporter);
+        report.set_breakdowns(&breakdown_reporter);
+        report.confidence_intervals = confidence_intervals;
+        report.comparison = comparison;
+        if let Some(stopwatch) = &stopwatch {
+            report.set_latency(stopwatch);
+        }
+        report
+            .write(report_path)
+            .unwrap_or_else(|err| panic!("Cannot write the report to {}: {}", report_path, err));
+        println!("report written to:{}", report_path);
+    }
+
+    if let Some(predictions) = predictions {
+        io::write_predictions(predictions_path, &predictions)
+            .unwrap_or_else(|err| panic!("Cannot write the predictions to {}: {}", predictions_path, err));
+        println!("predictions written to:{}", predictions_path);
+    }
+}
+
+/// The accumulated results of evaluating a part of the test sessions.
+#[derive(Clone)]
+struct Evaluation {
+    reporter: EvaluationReporter,
+    baseline_reporter: EvaluationReporter,
+    breakdown_reporter: BreakdownReporter,
+    scores: EventScores,
+    baseline_scores: EventScores,
+    /// The recommendations and remaining items of every test event, when they are written to a file.
+    predictions: Option<Vec<Prediction>>,
+}
+
+impl Evaluation {
+    /// Adds the results of the test sessions after the ones in `self`.
+    fn merge(&mut self, other: Evaluation) {
+        self.reporter.merge(other.reporter);
+        self.baseline_reporter.merge(other.baseline_reporter);
+        self.breakdown_reporter.merge(other.breakdown_reporter);
+        self.scores.merge(other.scores);
+        self.baseline_scores.merge(other.baseline_scores);
+        if let (Some(predictions), Some(other_predictions)) = (self.predictions.as_mut(), other.predictions) {
+            predictions.extend(other_predictions);
+        }
+    }
 }
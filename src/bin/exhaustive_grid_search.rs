@@ -1,11 +1,15 @@
 use serenade::config::AppConfig;
 use serenade::objective;
+use serenade::vmisknn::vmis_index::NeighborSimilarity;
 
 use indicatif::ProgressBar;
 
 extern crate csv;
 use csv::Writer;
 
+// The popularity weights tried are this many equal steps apart within `popularity_weight_range`.
+const POPULARITY_WEIGHT_STEPS: usize = 4;
+
 fn main() -> anyhow::Result<()>{
     // get params from config file
     let config_path = std::env::args().
@@ -17,15 +21,28 @@ fn main() -> anyhow::Result<()>{
     let save_records = config.hyperparam.save_records;
     let out_path = config.hyperparam.out_path;
     let enable_business_logic = config.hyperparam.enable_business_logic;
+    let dedup_consecutive_items = config.model.dedup_consecutive_items;
 
     // Possible values for hyperparameters
     let n_most_recent_sessions_choices = [100, 500, 1000, 2500];
     let neighborhood_size_k_choices = [50, 100, 500, 1000, 1500];
     let last_items_in_session_choices = [1, 2, 3, 5, 7, 10];
     let idf_weighting_choices = [1, 2, 3, 5, 7, 10];
+    let neighbor_similarity_choices = config.hyperparam.neighbor_similarity_choices;
+    let (min_popularity_weight, max_popularity_weight) = config.hyperparam.popularity_weight_range;
+    let popularity_weight_choices: Vec<f64> = if min_popularity_weight < max_popularity_weight {
+        (0..=POPULARITY_WEIGHT_STEPS)
+            .map(|step| {
+                min_popularity_weight
+                    + (max_popularity_weight - min_popularity_weight) * step as f64 / POPULARITY_WEIGHT_STEPS as f64
+            })
+            .collect()
+    } else {
+        vec![min_popularity_weight]
+    };
 
     // Progress bar
-    let total_num_iterations = n_most_recent_sessions_choices.len() * neighborhood_size_k_choices.len() * last_items_in_session_choices.len() * idf_weighting_choices.len();
+    let total_num_iterations = n_most_recent_sessions_choices.len() * neighborhood_size_k_choices.len() * last_items_in_session_choices.len() * idf_weighting_choices.len() * neighbor_similarity_choices.len() * popularity_weight_choices.len();
     let pb = ProgressBar::new(total_num_iterations as u64);
 
     let mut wtr = Writer::from_path(out_path)?;
@@ -37,6 +54,8 @@ fn main() -> anyhow::Result<()>{
             "neighborhood_size_k",
             "last_items_in_session",
             "idf_weighting",
+            "neighbor_similarity",
+            "popularity_weight",
             "MRR@20"
         ])?;
     }
@@ -48,6 +67,8 @@ fn main() -> anyhow::Result<()>{
     let mut best_neighborhood_size_k = -1;
     let mut best_last_items_in_session = -1;
     let mut best_idf_weighting = -1;
+    let mut best_neighbor_similarity = NeighborSimilarity::DotProduct;
+    let mut best_popularity_weight = min_popularity_weight;
     // let mut rng = rand::rngs::StdRng::from_seed(Default::default());
 
     // exhaustive grid search
@@ -55,41 +76,52 @@ fn main() -> anyhow::Result<()>{
         for neighborhood_size_k in neighborhood_size_k_choices {
             for last_items_in_session in last_items_in_session_choices {
                 for idf_weighting in idf_weighting_choices {
-                    // increment progress bar
-                    pb.inc(1);
-                    // get the result of the object function
-                    // with current combination of hyperparameters
-                    let v = objective::objective(
-                        train_data_path.clone(),
-                        test_data_path.clone(),
-                        n_most_recent_sessions,
-                        neighborhood_size_k,
-                        last_items_in_session,
-                        idf_weighting as f64,
-                        enable_business_logic
-                    );
+                    for neighbor_similarity in neighbor_similarity_choices.iter().copied() {
+                        for popularity_weight in popularity_weight_choices.iter().copied() {
+                            // increment progress bar
+                            pb.inc(1);
+                            // get the result of the object function
+                            // with current combination of hyperparameters
+                            let v = objective::objective(
+                                train_data_path.clone(),
+                                test_data_path.clone(),
+                                n_most_recent_sessions,
+                                neighborhood_size_k,
+                                last_items_in_session,
+                                idf_weighting as f64,
+                                neighbor_similarity,
+                                enable_business_logic,
+                                popularity_weight,
+                                dedup_consecutive_items,
+                            );
 
-                    if save_records {
-                        // Save current values
-                        wtr.write_record(&[
-                            (iteration as i32).to_string(),
-                            n_most_recent_sessions.to_string(),
-                            neighborhood_size_k.to_string(),
-                            last_items_in_session.to_string(),
-                            (idf_weighting as i32).to_string(),
-                            v.to_string()
-                        ])?;
-                    }
-                    // update current best values
-                    if v > best_value {
-                        best_value = v;
-                        best_n_most_recent_sessions = n_most_recent_sessions;
-                        best_neighborhood_size_k = neighborhood_size_k;
-                        best_last_items_in_session = last_items_in_session;
-                        best_idf_weighting = idf_weighting;
-                    }
-                    iteration = iteration + 1;
+                            if save_records {
+                                // Save current values
+                                wtr.write_record(&[
+                                    (iteration as i32).to_string(),
+                                    n_most_recent_sessions.to_string(),
+                                    neighborhood_size_k.to_string(),
+                                    last_items_in_session.to_string(),
+                                    (idf_weighting as i32).to_string(),
+                                    neighbor_similarity.to_string(),
+                                    popularity_weight.to_string(),
+                                    v.to_string()
+                                ])?;
+                            }
+                            // update current best values
+                            if v > best_value {
+                                best_value = v;
+                                best_n_most_recent_sessions = n_most_recent_sessions;
+                                best_neighborhood_size_k = neighborhood_size_k;
+                                best_last_items_in_session = last_items_in_session;
+                                best_idf_weighting = idf_weighting;
+                                best_neighbor_similarity = neighbor_similarity;
+                                best_popularity_weight = popularity_weight;
+                            }
+                            iteration = iteration + 1;
 
+                        }
+                    }
                 }
             }
         }
@@ -103,6 +135,10 @@ fn main() -> anyhow::Result<()>{
         best_last_items_in_session);
     println!("Best idf_weighting: {}",
              best_idf_weighting);
+    println!("Best neighbor_similarity: {}",
+             best_neighbor_similarity);
+    println!("Best popularity_weight: {}",
+             best_popularity_weight);
     if enable_business_logic {
         println!("Business logic were enabled.");
     } else {
@@ -72,7 +72,9 @@ fn main() {
                             m_most_recent_sessions,
                             qty_max_reco_results,
                             enable_business_logic,
-                        );
+                            false,
+                        )
+                        .expect("evolving test sessions hold at least one item");
 
                         let recommended_items = recommendations
                             .into_sorted_vec()
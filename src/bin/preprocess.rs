@@ -0,0 +1,55 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use serenade::config::AppConfig;
+use serenade::io::write_training_data;
+use serenade::preprocessing::{preprocess, read_raw_interactions, PreprocessingOptions};
+
+// Cleans a raw interaction log with the filters in the `[preprocessing]` section of the config file
+// and writes it in the training data format, ready for `split_data`. The raw log has a header and
+// `SessionId ItemId Time` rows, or `UserId ItemId Time` rows when `session_gap_in_secs` is set.
+//
+// usage:
+//   preprocess example.toml
+fn main() {
+    let config_path = std::env::args().nth(1).expect("Config file not specified!");
+    let config = AppConfig::new(config_path).preprocessing;
+    if config.input_path.is_empty() || config.output_path.is_empty() {
+        panic!("Set input_path and output_path in the [preprocessing] section");
+    }
+    let options = PreprocessingOptions {
+        session_gap_in_secs: config.session_gap_in_secs,
+        dedup_consecutive_items: config.dedup_consecutive_items,
+        min_item_support: config.min_item_support,
+        min_session_length: config.min_session_length,
+        max_session_length: config.max_session_length,
+        remap_ids: config.remap_ids,
+    };
+    println!("{:?}", options);
+
+    let (raw_interactions, qty_unparsable_rows) = read_raw_interactions(&config.input_path)
+        .unwrap_or_else(|err| panic!("Cannot read {}: {}", config.input_path, err));
+    let mut preprocessed = preprocess(raw_interactions, &options).unwrap_or_else(|err| panic!("{}", err));
+    preprocessed.summary.qty_unparsable_rows = qty_unparsable_rows;
+
+    write_training_data(&config.output_path, &preprocessed.interactions)
+        .unwrap_or_else(|err| panic!("Cannot write {}: {}", config.output_path, err));
+    println!("output written to:{}", config.output_path);
+    if options.remap_ids && !config.item_mapping_path.is_empty() {
+        write_item_mapping(&config.item_mapping_path, &preprocessed.item_id_mapping)
+            .unwrap_or_else(|err| panic!("Cannot write {}: {}", config.item_mapping_path, err));
+        println!("item id mapping written to:{}", config.item_mapping_path);
+    }
+    for line in preprocessed.summary.lines() {
+        println!("{}", line);
+    }
+}
+
+fn write_item_mapping(path: &str, item_id_mapping: &[(u64, u64)]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "ItemId\tOriginalItemId")?;
+    for (item_id, original_item_id) in item_id_mapping.iter() {
+        writeln!(writer, "{}\t{}", item_id, original_item_id)?;
+    }
+    writer.flush()
+}
@@ -1,11 +1,13 @@
 extern crate serenade;
 
+use sessions::user_profiles::RocksDBUserProfileStore;
 use sessions::RocksDBSessionStore;
 
 use actix_web::{
     http::ContentEncoding, middleware, web, App, HttpRequest, HttpResponse, HttpServer,
 };
 use actix_web_prom::PrometheusMetrics;
+use prometheus::{IntCounter, IntCounterVec, Opts};
 
 use actix_web::http::header;
 use std::path::Path;
@@ -15,9 +17,20 @@ use std::time::Duration;
 use serenade::config::AppConfig;
 use serenade::dataframeutils::SharedHandlesAndConfig;
 use serenade::endpoints::index_resource::internal;
+use serenade::endpoints::popular_resource::v1_popular;
 use serenade::endpoints::recommend_resource::v1_recommend;
+use serenade::endpoints::similar_resource::v1_similar;
+use serenade::endpoints::session_resource::{
+    v1_bulk_delete_sessions, v1_delete_session, v1_delete_user_profile,
+};
+use serenade::io;
+use serenade::popularity::{LazyLivePopularityIndex, PopularityIndex};
 use serenade::sessions;
-use serenade::vmisknn::vmis_index::VMISIndex;
+use serenade::vmisknn::diversity::{Diversifier, DiversitySimilarity};
+use serenade::vmisknn::fallback::{FallbackRecommender, FallbackStrategy};
+use serenade::vmisknn::similar_items::{ItemSimilarity, SimilarItems};
+use serenade::recommender::{create_recommender, uses_popularity, validate_training_data, Algorithm};
+use serenade::vmisknn::vmis_index::{NeighborSimilarity, VMISIndex};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -29,48 +42,180 @@ async fn main() -> std::io::Result<()> {
     let neighborhood_size_k = config.model.neighborhood_size_k;
     let num_items_to_recommend = config.model.num_items_to_recommend;
     let max_items_in_session = config.model.max_items_in_session;
+    let dedup_consecutive_items = config.model.dedup_consecutive_items;
     let qty_workers = config.server.num_workers;
     let enable_business_logic = config.logic.enable_business_logic;
     let example_item_id = config.logic.example_item_id;
 
+    let algorithm: Algorithm = config
+        .model
+        .algorithm
+        .parse()
+        .unwrap_or_else(|err| panic!("{}", err));
+    validate_training_data(algorithm, &config.ensemble, &config.data.training_data_path)
+        .unwrap_or_else(|err| panic!("{}", err));
+
     let training_data_path = Path::new(&config.data.training_data_path);
     let vmis_index = if training_data_path.is_dir() {
         // By default we use an index that is computed offline on billions of user-item interactions.
-        Arc::new(VMISIndex::new(&config.data.training_data_path))
+        VMISIndex::new(&config.data.training_data_path)
     } else if training_data_path.is_file() {
         // The following line creates an index directly from a csv file as input.
-        Arc::new(VMISIndex::new_from_csv(
+        VMISIndex::new_from_csv(
             &config.data.training_data_path,
             config.model.m_most_recent_sessions,
             config.model.idf_weighting as f64,
-        ))
+        )
     } else {
         panic!(
             "Training data file does not exist: {}",
             &config.data.training_data_path
         )
     };
+    let neighbor_similarity: NeighborSimilarity = config
+        .model
+        .neighbor_similarity
+        .parse()
+        .unwrap_or_else(|err| panic!("{}", err));
+    let vmis_index = Arc::new(vmis_index.with_neighbor_similarity(neighbor_similarity));
+
+    let fallback_strategy: FallbackStrategy = config
+        .logic
+        .fallback_strategy
+        .parse()
+        .unwrap_or_else(|err| panic!("{}", err));
+    let fallback_recommender = Arc::new(FallbackRecommender::new(
+        &vmis_index,
+        fallback_strategy,
+        config.logic.trending_window_in_secs,
+    ));
+
+    let popularity_training_data_path = config.data.training_data_path.clone();
+    let popularity_vmis_index = vmis_index.clone();
+    let popularity_window_in_secs = config.popularity.window_in_secs;
+    let popularity_index = Arc::new(LazyLivePopularityIndex::new(move || {
+        let training_data = if Path::new(&popularity_training_data_path).is_file() {
+            io::read_training_data(&popularity_training_data_path)
+        } else {
+            popularity_vmis_index.session_interactions()
+        };
+        PopularityIndex::new(&training_data, popularity_window_in_secs)
+    }));
+    let include_live_events_in_popularity = config.popularity.include_live_events;
+    // Otherwise the popularity model is only built by the first request to `/v1/popular`.
+    if include_live_events_in_popularity
+        || uses_popularity(algorithm, &config.ensemble).unwrap_or_else(|err| panic!("{}", err))
+    {
+        println!("build popularity index");
+        popularity_index.get();
+    }
+
+    let recommender = create_recommender(
+        algorithm,
+        vmis_index.clone(),
+        popularity_index.clone(),
+        &config.data.training_data_path,
+        &config.model,
+        &config.ensemble,
+    );
+
+    let item_similarity: ItemSimilarity = config
+        .similar_items
+        .similarity
+        .parse()
+        .unwrap_or_else(|err| panic!("{}", err));
+    println!("precompute similar items");
+    let similar_items = Arc::new(SimilarItems::new(
+        &vmis_index,
+        item_similarity,
+        config.similar_items.qty_precomputed_items,
+    ));
+
+    let diversity_similarity: DiversitySimilarity = config
+        .diversity
+        .similarity
+        .parse()
+        .unwrap_or_else(|err| panic!("{}", err));
+    let diversifier = Arc::new(Diversifier::new(diversity_similarity, config.diversity.lambda));
 
     println!("start db");
     let session_ttl = Duration::from_secs(30 * 60);
     let db = Arc::new(RocksDBSessionStore::new("./sessions.db", session_ttl));
+    let user_profile_ttl = Duration::from_secs(90 * 24 * 60 * 60);
+    let user_profile_db = Arc::new(RocksDBUserProfileStore::new(
+        "./user_profiles.db",
+        user_profile_ttl,
+        config.model.max_items_in_user_history,
+    ));
 
     println!("start metrics");
     let prometheus = PrometheusMetrics::new("api", Some("/internal/prometheus"), None);
+    let session_store_failures = IntCounterVec::new(
+        Opts::new("session_store_failures_total", "Failed session store operations")
+            .namespace("api"),
+        &["operation"],
+    )
+    .unwrap();
+    prometheus
+        .registry
+        .register(Box::new(session_store_failures.clone()))
+        .unwrap();
+    let prediction_failures = IntCounter::with_opts(
+        Opts::new("prediction_failures_total", "Evolving sessions that could not be scored").namespace("api"),
+    )
+    .unwrap();
+    prometheus
+        .registry
+        .register(Box::new(prediction_failures.clone()))
+        .unwrap();
+    let session_erasures = IntCounterVec::new(
+        Opts::new("session_erasures_total", "Sessions erased on request, by outcome")
+            .namespace("api"),
+        &["outcome"],
+    )
+    .unwrap();
+    prometheus
+        .registry
+        .register(Box::new(session_erasures.clone()))
+        .unwrap();
+    let user_profile_erasures = IntCounterVec::new(
+        Opts::new("user_profile_erasures_total", "User histories erased on request, by outcome")
+            .namespace("api"),
+        &["outcome"],
+    )
+    .unwrap();
+    prometheus
+        .registry
+        .register(Box::new(user_profile_erasures.clone()))
+        .unwrap();
 
     println!("Done. start httpd at http://{}", &bind_address);
     HttpServer::new(move || {
         let handles_and_config = SharedHandlesAndConfig {
             session_store: db.clone(),
+            user_profile_store: user_profile_db.clone(),
             vmis_index: vmis_index.clone(),
+            recommender: recommender.clone(),
+            algorithm,
+            fallback_recommender: fallback_recommender.clone(),
+            popularity_index: popularity_index.clone(),
+            similar_items: similar_items.clone(),
+            diversifier: diversifier.clone(),
+            include_live_events_in_popularity,
             m_most_recent_sessions,
             neighborhood_size_k,
             num_items_to_recommend,
             max_items_in_session,
+            dedup_consecutive_items,
             qty_workers,
             db_compaction_ttl_in_secs: session_ttl.as_secs() as usize,
+            user_profile_ttl_in_secs: user_profile_ttl.as_secs() as usize,
             enable_business_logic,
             example_item_id,
+            session_store_failures: session_store_failures.clone(),
+            prediction_failures: prediction_failures.clone(),
+            session_erasures: session_erasures.clone(),
+            user_profile_erasures: user_profile_erasures.clone(),
         };
 
         App::new()
@@ -84,6 +229,11 @@ async fn main() -> std::io::Result<()> {
             )
             .data(handles_and_config)
             .service(v1_recommend)
+            .service(v1_popular)
+            .service(v1_similar)
+            .service(v1_delete_session)
+            .service(v1_bulk_delete_sessions)
+            .service(v1_delete_user_profile)
             .service(internal)
             .service(web::resource("/").route(web::get().to(|_req: HttpRequest| {
                 HttpResponse::Found()
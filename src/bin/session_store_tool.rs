@@ -0,0 +1,111 @@
+use itertools::Itertools;
+use serenade::io::{ItemId, TrainingSessionId};
+use serenade::sessions::{evolving_session_id, RocksDBSessionStore};
+use std::time::Duration;
+
+extern crate csv;
+use csv::{ReaderBuilder, WriterBuilder};
+
+// Exports the live sessions of a session store to a TSV file and imports such a file back.
+// The file uses the training data format (SessionId, ItemId, Time) that `io::read_training_data` reads,
+// with an additional SessionKey column holding the key of the evolving session in the store.
+// Serenade's serving binary must be stopped, RocksDB does not allow two processes to open the same database.
+//
+// usage:
+//   session_store_tool export ./sessions.db sessions.txt
+//   session_store_tool import ./sessions.db sessions.txt
+fn main() -> anyhow::Result<()> {
+    let command = std::env::args()
+        .nth(1)
+        .expect("Command not specified, use 'export' or 'import'!");
+    let database_path = std::env::args()
+        .nth(2)
+        .expect("Session store database not specified!");
+    let tsv_path = std::env::args().nth(3).expect("TSV file not specified!");
+
+    let session_ttl = Duration::from_secs(30 * 60);
+    let session_store = RocksDBSessionStore::new(&database_path, session_ttl);
+
+    match command.as_str() {
+        "export" => export_sessions(&session_store, &tsv_path),
+        "import" => import_sessions(&session_store, &tsv_path),
+        _ => panic!("Unknown command: {}, use 'export' or 'import'", command),
+    }
+}
+
+fn export_sessions(session_store: &RocksDBSessionStore, tsv_path: &str) -> anyhow::Result<()> {
+    let mut wtr = WriterBuilder::new().delimiter(b'\t').from_path(tsv_path)?;
+    wtr.write_record(["SessionId", "ItemId", "Time", "SessionKey"])?;
+
+    let mut qty_sessions: TrainingSessionId = 0;
+    let mut qty_failures = 0;
+    for entry in session_store.live_sessions() {
+        match entry {
+            Ok((session_key, payload)) => {
+                // The store only keeps the time of the last event. Earlier items get one second
+                // less per position so the item order survives sorting by time.
+                let qty_items = payload.session_items.len() as u64;
+                for (pos, item_id) in payload.session_items.iter().enumerate() {
+                    let time = payload
+                        .epoch_secs
+                        .saturating_sub(qty_items - 1 - pos as u64);
+                    wtr.write_record(&[
+                        qty_sessions.to_string(),
+                        item_id.to_string(),
+                        time.to_string(),
+                        session_key.to_string(),
+                    ])?;
+                }
+                qty_sessions += 1;
+            }
+            Err(err) => {
+                eprintln!("Skipping session: {}", err);
+                qty_failures += 1;
+            }
+        }
+    }
+    wtr.flush()?;
+
+    println!("Exported {} live sessions to {}", qty_sessions, tsv_path);
+    if qty_failures > 0 {
+        println!("Skipped {} unreadable sessions", qty_failures);
+    }
+    Ok(())
+}
+
+fn import_sessions(session_store: &RocksDBSessionStore, tsv_path: &str) -> anyhow::Result<()> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .flexible(true)
+        .from_path(tsv_path)?;
+
+    let mut events: Vec<(u128, (ItemId, u64))> = Vec::new();
+    for result in reader.records() {
+        let record = result?;
+        let session_id = record.get(0).unwrap_or_default();
+        // Without a SessionKey column the session id is hashed the same way as the v1/recommend endpoint does.
+        let session_key = match record.get(3) {
+            Some(session_key) => session_key.parse::<u128>()?,
+            None => evolving_session_id(session_id),
+        };
+        let item_id = record.get(1).unwrap_or_default().parse::<ItemId>()?;
+        let time = record.get(2).unwrap_or_default().parse::<f64>()?.round() as u64;
+        events.push((session_key, (item_id, time)));
+    }
+
+    let sessions = events.into_iter().into_group_map();
+    let qty_sessions = sessions.len();
+    for (session_key, mut items_with_time) in sessions.into_iter() {
+        items_with_time.sort_by_key(|(_item_id, time)| *time);
+        let epoch_secs = items_with_time.last().map(|(_item_id, time)| *time).unwrap_or(0);
+        let session_items = items_with_time
+            .into_iter()
+            .map(|(item_id, _time)| item_id)
+            .collect_vec();
+        session_store.import_session(&session_key, &session_items, epoch_secs)?;
+    }
+
+    println!("Imported {} sessions from {}", qty_sessions, tsv_path);
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use std::path::Path;
+
+use itertools::Itertools;
+use serenade::io::{read_training_data, write_training_data};
+use serenade::split::split_last_days;
+
+// Splits raw interactions (SessionId, ItemId, Time) by time into the training, validation and test
+// files of the evaluator and the hyperparameter search, like the session-rec preprocessing:
+//   train_full.txt  all sessions before the test days
+//   test.txt        the sessions that end in the last `test_days`
+//   train.txt       the sessions of train_full.txt before the validation days
+//   valid.txt       the sessions of train_full.txt that end in the last `validation_days`
+//
+// usage:
+//   split_data interactions.txt ./data 1 1
+fn main() {
+    let interactions_path = std::env::args()
+        .nth(1)
+        .expect("Interactions file not specified!");
+    let output_dir = std::env::args().nth(2).expect("Output directory not specified!");
+    let parse_days = |position: usize| -> usize {
+        std::env::args()
+            .nth(position)
+            .map(|days| days.parse().expect("The number of days must be a number"))
+            .unwrap_or(1)
+    };
+    let test_days = parse_days(3);
+    let validation_days = parse_days(4);
+
+    let interactions = read_training_data(&interactions_path);
+    let (train_full, test) = split_last_days(&interactions, test_days);
+    let (train, valid) = split_last_days(&train_full, validation_days);
+
+    for (name, split) in [
+        ("train_full.txt", &train_full),
+        ("test.txt", &test),
+        ("train.txt", &train),
+        ("valid.txt", &valid),
+    ] {
+        let path = Path::new(&output_dir).join(name);
+        let path = path.to_str().unwrap();
+        write_training_data(path, split).unwrap_or_else(|err| panic!("Cannot write {}: {}", path, err));
+        let qty_sessions = split.iter().map(|(session_id, _, _)| session_id).unique().count();
+        println!("{}: {} interactions in {} sessions", path, split.len(), qty_sessions);
+    }
+}
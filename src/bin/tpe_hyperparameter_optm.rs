@@ -35,6 +35,7 @@ fn main() {
     let save_records = config.hyperparam.save_records;
     let out_path = config.hyperparam.out_path;
     let enable_business_logic = config.hyperparam.enable_business_logic;
+    let dedup_consecutive_items = config.model.dedup_consecutive_items;
     let n_most_recent_sessions_range = convert_string_to_vec_i32(
         config.hyperparam.n_most_recent_sessions_range);
     let neighborhood_size_k_range = convert_string_to_vec_i32(
@@ -43,6 +44,8 @@ fn main() {
         config.hyperparam.last_items_in_session_range);
     let idf_weighting_range = convert_string_to_vec_i32(
         config.hyperparam.idf_weighting_range);
+    let (min_popularity_weight, max_popularity_weight) = config.hyperparam.popularity_weight_range;
+    let neighbor_similarity_choices = config.hyperparam.neighbor_similarity_choices;
 
     // Progress bar
     let pb = ProgressBar::new(num_iterations as u64);
@@ -55,6 +58,8 @@ fn main() {
             "neighborhood_size_k",
             "last_items_in_session",
             "idf_weighting",
+            "popularity_weight",
+            "neighbor_similarity",
             "MRR@20"
         ]).unwrap();
     }
@@ -81,6 +86,20 @@ fn main() {
         // last items from session
         Arc::new(Mutex::new(tpe::TpeOptimizer::new(tpe::parzen_estimator(), tpe::range(idf_weighting_range.first().unwrap().clone() as f64, idf_weighting_range.last().unwrap().clone() as f64).unwrap())));
 
+    // popularity weight in the blend with VMIS-kNN, only searched when the range is not empty
+    let optim4 = if min_popularity_weight < max_popularity_weight {
+        Some(Arc::new(Mutex::new(tpe::TpeOptimizer::new(tpe::parzen_estimator(), tpe::range(min_popularity_weight, max_popularity_weight).unwrap()))))
+    } else {
+        None
+    };
+
+    // neighbor similarity normalization, a categorical choice only searched when there is a choice
+    let optim5 = if neighbor_similarity_choices.len() > 1 {
+        Some(Arc::new(Mutex::new(tpe::TpeOptimizer::new(tpe::histogram_estimator(), tpe::categorical_range(neighbor_similarity_choices.len()).unwrap()))))
+    } else {
+        None
+    };
+
     println!("===============================================================");
     println!("===           START HYPER PARAMETER OPTIMIZATION           ====");
     println!("===============================================================");
@@ -106,6 +125,15 @@ fn main() {
         let neighborhood_size_k = optim1_clone.lock().unwrap().ask(&mut rng).unwrap();
         let last_items_in_session = optim2_clone.lock().unwrap().ask(&mut rng).unwrap();
         let idf_weighting = optim3_clone.lock().unwrap().ask(&mut rng).unwrap().floor();
+        let popularity_weight = match &optim4 {
+            Some(optim) => optim.lock().unwrap().ask(&mut rng).unwrap(),
+            None => min_popularity_weight,
+        };
+        let neighbor_similarity_choice = match &optim5 {
+            Some(optim) => optim.lock().unwrap().ask(&mut rng).unwrap(),
+            None => 0.0,
+        };
+        let neighbor_similarity = neighbor_similarity_choices[neighbor_similarity_choice as usize];
         let best_value_clone = Arc::clone(&best_value);
 
         // Unlock the mutexes to allow other threads to access optim's
@@ -123,7 +151,10 @@ fn main() {
             neighborhood_size_k as i32,
             last_items_in_session as i32,
             idf_weighting,
-            enable_business_logic
+            neighbor_similarity,
+            enable_business_logic,
+            popularity_weight,
+            dedup_consecutive_items,
         );
 
 
@@ -137,6 +168,8 @@ fn main() {
                 neighborhood_size_k.to_string(),
                 last_items_in_session.to_string(),
                 idf_weighting.to_string(),
+                popularity_weight.to_string(),
+                neighbor_similarity.to_string(),
                 v.to_string()
             ])
                 .unwrap();
@@ -155,6 +188,12 @@ fn main() {
         optim1_clone.lock().unwrap().tell(neighborhood_size_k, v).unwrap();
         optim2_clone.lock().unwrap().tell(last_items_in_session, v).unwrap();
         optim3_clone.lock().unwrap().tell(idf_weighting, v).unwrap();
+        if let Some(optim) = &optim4 {
+            optim.lock().unwrap().tell(popularity_weight, v).unwrap();
+        }
+        if let Some(optim) = &optim5 {
+            optim.lock().unwrap().tell(neighbor_similarity_choice, v).unwrap();
+        }
 
 
         // update current best_value
@@ -180,6 +219,16 @@ fn main() {
         .find(|(_value, score)| score == &final_best_value).map(|(value, _score)| value as i32).unwrap();
     let idf_weighting = optim3.lock().unwrap().trials().collect_vec().into_iter()
         .find(|(_value, score)| score == &final_best_value).map(|(value, _score)| value.floor()).unwrap();
+    let popularity_weight = match &optim4 {
+        Some(optim) => optim.lock().unwrap().trials().collect_vec().into_iter()
+            .find(|(_value, score)| score == &final_best_value).map(|(value, _score)| value).unwrap(),
+        None => min_popularity_weight,
+    };
+    let neighbor_similarity = match &optim5 {
+        Some(optim) => optim.lock().unwrap().trials().collect_vec().into_iter()
+            .find(|(_value, score)| score == &final_best_value).map(|(value, _score)| neighbor_similarity_choices[value as usize]).unwrap(),
+        None => neighbor_similarity_choices[0],
+    };
 
     let test_score = objective::objective(
         training_data_path.clone(),
@@ -188,7 +237,10 @@ fn main() {
         neighborhood_size_k,
         last_items_in_session,
         idf_weighting ,
+        neighbor_similarity,
         enable_business_logic,
+        popularity_weight,
+        dedup_consecutive_items,
     );
 
 
@@ -206,6 +258,8 @@ fn main() {
     println!("neighborhood_size_k:{}", neighborhood_size_k);
     println!("idf_weighting:{}", idf_weighting);
     println!("last_items_in_session:{}", last_items_in_session);
+    println!("popularity_weight:{}", popularity_weight);
+    println!("neighbor_similarity:{}", neighbor_similarity);
 
     println!("HPO done");
     stdout().flush().unwrap();
@@ -10,6 +10,7 @@ use justconfig::ConfPath;
 use justconfig::Config;
 
 use crate::config_processors::Unquote;
+use crate::vmisknn::vmis_index::{parse_neighbor_similarities, NeighborSimilarity};
 
 // Set some default values
 const DEFAULT_MOST_RECENT_SESSIONS_M: usize = 500;
@@ -17,6 +18,34 @@ const DEFAULT_NEIGHBORHOOD_SIZE_K: usize = 500;
 const DEFAULT_NUM_ITEMS_TO_RECOMMEND: usize = 21;
 const DEFAULT_MAX_ITEMS_IN_SESSION: usize = 2;
 const DEFAULT_IDF_WEIGHTING: usize = 1;
+const DEFAULT_MAX_ITEMS_IN_USER_HISTORY: usize = 20;
+const DEFAULT_ALGORITHM: &str = "vmis_knn";
+const DEFAULT_RULES_MAX_STEPS: usize = 10;
+const DEFAULT_DEDUP_CONSECUTIVE_ITEMS: bool = false;
+const DEFAULT_NEIGHBOR_SIMILARITY: &str = "dot_product";
+const DEFAULT_FALLBACK_STRATEGY: &str = "none";
+const DEFAULT_TRENDING_WINDOW_IN_SECS: u32 = 24 * 60 * 60;
+const DEFAULT_POPULARITY_WINDOW_IN_SECS: usize = 0;
+const DEFAULT_ENSEMBLE_MEMBERS: &str = "vmis_knn:1.0";
+const DEFAULT_SCORE_NORMALIZATION: &str = "min_max";
+const DEFAULT_POPULARITY_WEIGHT_RANGE: (f64, f64) = (0.0, 0.0);
+const DEFAULT_DIVERSITY_LAMBDA: f64 = 1.0;
+const DEFAULT_DIVERSITY_SIMILARITY: &str = "co_occurrence";
+const DEFAULT_ITEM_SIMILARITY: &str = "cosine";
+const DEFAULT_QTY_PRECOMPUTED_SIMILAR_ITEMS: usize = 0;
+const DEFAULT_MEASURE_LATENCY: bool = false;
+const DEFAULT_REPORT_PATH: &str = "";
+const DEFAULT_CUTOFFS: &str = "";
+const DEFAULT_EVALUATION_PROTOCOL: &str = "remaining_items";
+const DEFAULT_BOOTSTRAP_RESAMPLES: usize = 0;
+const DEFAULT_CONFIDENCE_LEVEL: f64 = 0.95;
+const DEFAULT_PREDICTIONS_PATH: &str = "";
+const DEFAULT_SESSION_GAP_IN_SECS: usize = 0;
+const DEFAULT_PREPROCESSING_DEDUP_CONSECUTIVE_ITEMS: bool = true;
+const DEFAULT_MIN_ITEM_SUPPORT: usize = 5;
+const DEFAULT_MIN_SESSION_LENGTH: usize = 2;
+const DEFAULT_MAX_SESSION_LENGTH: usize = 0;
+const DEFAULT_REMAP_IDS: bool = false;
 
 pub struct AppConfig {
     pub server: ServerConfig,
@@ -24,6 +53,12 @@ pub struct AppConfig {
     pub data: DataConfig,
     pub model: ModelConfig,
     pub logic: LogicConfig,
+    pub popularity: PopularityConfig,
+    pub similar_items: SimilarItemsConfig,
+    pub ensemble: EnsembleConfig,
+    pub diversity: DiversityConfig,
+    pub evaluation: EvaluationConfig,
+    pub preprocessing: PreprocessingConfig,
     pub hyperparam: HyperparamConfig
 }
 
@@ -47,11 +82,63 @@ pub struct ModelConfig {
     pub num_items_to_recommend: usize,
     pub max_items_in_session: usize,
     pub idf_weighting: usize,
+    pub max_items_in_user_history: usize,
+    pub algorithm: String,
+    pub rules_max_steps: usize,
+    pub dedup_consecutive_items: bool,
+    pub neighbor_similarity: String,
 }
 
 pub struct LogicConfig {
     pub enable_business_logic: bool,
     pub example_item_id: usize,
+    pub fallback_strategy: String,
+    pub trending_window_in_secs: u32,
+}
+
+pub struct PopularityConfig {
+    pub window_in_secs: usize,
+    pub include_live_events: bool,
+}
+
+pub struct SimilarItemsConfig {
+    pub similarity: String,
+    pub qty_precomputed_items: usize,
+}
+
+pub struct EnsembleConfig {
+    pub members: String,
+    pub normalization: String,
+}
+
+pub struct DiversityConfig {
+    pub lambda: f64,
+    pub similarity: String,
+}
+
+pub struct EvaluationConfig {
+    pub measure_latency: bool,
+    pub report_path: String,
+    /// Comma separated cutoffs of the metrics, empty for `num_items_to_recommend` only.
+    pub cutoffs: String,
+    pub protocol: String,
+    /// Resamples of the bootstrap confidence intervals, `0` to skip them.
+    pub bootstrap_resamples: usize,
+    pub confidence_level: f64,
+    pub predictions_path: String,
+}
+
+pub struct PreprocessingConfig {
+    pub input_path: String,
+    pub output_path: String,
+    /// Where the `(new id, original id)` pairs of the items are written when the ids are remapped.
+    pub item_mapping_path: String,
+    pub session_gap_in_secs: usize,
+    pub dedup_consecutive_items: bool,
+    pub min_item_support: usize,
+    pub min_session_length: usize,
+    pub max_session_length: usize,
+    pub remap_ids: bool,
 }
 
 pub struct HyperparamConfig {
@@ -66,6 +153,9 @@ pub struct HyperparamConfig {
     pub neighborhood_size_k_range: String,
     pub last_items_in_session_range: String,
     pub idf_weighting_range: String,
+    /// The lowest and highest popularity weight to search.
+    pub popularity_weight_range: (f64, f64),
+    pub neighbor_similarity_choices: Vec<NeighborSimilarity>,
 }
 
 impl AppConfig {
@@ -104,6 +194,12 @@ impl AppConfig {
             data: DataConfig::parse(&conf, ConfPath::from(&["data"])),
             model: ModelConfig::parse(&conf, ConfPath::from(&["model"])),
             logic: LogicConfig::parse(&conf, ConfPath::from(&["logic"])),
+            popularity: PopularityConfig::parse(&conf, ConfPath::from(&["popularity"])),
+            similar_items: SimilarItemsConfig::parse(&conf, ConfPath::from(&["similar_items"])),
+            ensemble: EnsembleConfig::parse(&conf, ConfPath::from(&["ensemble"])),
+            diversity: DiversityConfig::parse(&conf, ConfPath::from(&["diversity"])),
+            evaluation: EvaluationConfig::parse(&conf, ConfPath::from(&["evaluation"])),
+            preprocessing: PreprocessingConfig::parse(&conf, ConfPath::from(&["preprocessing"])),
             hyperparam: HyperparamConfig::parse(&conf, ConfPath::from(&["hyperparam"]))
         }
     }
@@ -180,6 +276,31 @@ impl ModelConfig {
                 .trim()
                 .value()
                 .unwrap_or(DEFAULT_IDF_WEIGHTING),
+            max_items_in_user_history: conf
+                .get(path.push("max_items_in_user_history"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_MAX_ITEMS_IN_USER_HISTORY),
+            algorithm: conf
+                .get(path.push("algorithm"))
+                .unquote()
+                .value()
+                .unwrap_or_else(|_| String::from(DEFAULT_ALGORITHM)),
+            rules_max_steps: conf
+                .get(path.push("rules_max_steps"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_RULES_MAX_STEPS),
+            dedup_consecutive_items: conf
+                .get(path.push("dedup_consecutive_items"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_DEDUP_CONSECUTIVE_ITEMS),
+            neighbor_similarity: conf
+                .get(path.push("neighbor_similarity"))
+                .unquote()
+                .value()
+                .unwrap_or_else(|_| String::from(DEFAULT_NEIGHBOR_SIMILARITY)),
         }
     }
 }
@@ -197,6 +318,178 @@ impl LogicConfig {
                 .trim()
                 .value()
                 .unwrap_or(0),
+            fallback_strategy: conf
+                .get(path.push("fallback_strategy"))
+                .unquote()
+                .value()
+                .unwrap_or_else(|_| String::from(DEFAULT_FALLBACK_STRATEGY)),
+            trending_window_in_secs: conf
+                .get(path.push("trending_window_in_secs"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_TRENDING_WINDOW_IN_SECS),
+        }
+    }
+}
+
+impl PopularityConfig {
+    fn parse(conf: &Config, path: ConfPath) -> PopularityConfig {
+        PopularityConfig {
+            window_in_secs: conf
+                .get(path.push("window_in_secs"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_POPULARITY_WINDOW_IN_SECS),
+            include_live_events: conf
+                .get(path.push("include_live_events"))
+                .trim()
+                .value()
+                .unwrap_or(false),
+        }
+    }
+}
+
+impl SimilarItemsConfig {
+    fn parse(conf: &Config, path: ConfPath) -> SimilarItemsConfig {
+        SimilarItemsConfig {
+            similarity: conf
+                .get(path.push("similarity"))
+                .unquote()
+                .value()
+                .unwrap_or_else(|_| String::from(DEFAULT_ITEM_SIMILARITY)),
+            qty_precomputed_items: conf
+                .get(path.push("qty_precomputed_items"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_QTY_PRECOMPUTED_SIMILAR_ITEMS),
+        }
+    }
+}
+
+impl EnsembleConfig {
+    fn parse(conf: &Config, path: ConfPath) -> EnsembleConfig {
+        EnsembleConfig {
+            members: conf
+                .get(path.push("members"))
+                .unquote()
+                .value()
+                .unwrap_or_else(|_| String::from(DEFAULT_ENSEMBLE_MEMBERS)),
+            normalization: conf
+                .get(path.push("normalization"))
+                .unquote()
+                .value()
+                .unwrap_or_else(|_| String::from(DEFAULT_SCORE_NORMALIZATION)),
+        }
+    }
+}
+
+impl DiversityConfig {
+    fn parse(conf: &Config, path: ConfPath) -> DiversityConfig {
+        DiversityConfig {
+            lambda: conf
+                .get(path.push("lambda"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_DIVERSITY_LAMBDA),
+            similarity: conf
+                .get(path.push("similarity"))
+                .unquote()
+                .value()
+                .unwrap_or_else(|_| String::from(DEFAULT_DIVERSITY_SIMILARITY)),
+        }
+    }
+}
+
+impl EvaluationConfig {
+    fn parse(conf: &Config, path: ConfPath) -> EvaluationConfig {
+        EvaluationConfig {
+            measure_latency: conf
+                .get(path.push("measure_latency"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_MEASURE_LATENCY),
+            report_path: conf
+                .get(path.push("report_path"))
+                .unquote()
+                .value()
+                .unwrap_or_else(|_| String::from(DEFAULT_REPORT_PATH)),
+            cutoffs: conf
+                .get(path.push("cutoffs"))
+                .unquote()
+                .value()
+                .unwrap_or_else(|_| String::from(DEFAULT_CUTOFFS)),
+            protocol: conf
+                .get(path.push("protocol"))
+                .unquote()
+                .value()
+                .unwrap_or_else(|_| String::from(DEFAULT_EVALUATION_PROTOCOL)),
+            bootstrap_resamples: conf
+                .get(path.push("bootstrap_resamples"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_BOOTSTRAP_RESAMPLES),
+            confidence_level: conf
+                .get(path.push("confidence_level"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_CONFIDENCE_LEVEL),
+            predictions_path: conf
+                .get(path.push("predictions_path"))
+                .unquote()
+                .value()
+                .unwrap_or_else(|_| String::from(DEFAULT_PREDICTIONS_PATH)),
+        }
+    }
+}
+
+impl PreprocessingConfig {
+    fn parse(conf: &Config, path: ConfPath) -> PreprocessingConfig {
+        PreprocessingConfig {
+            input_path: conf
+                .get(path.push("input_path"))
+                .unquote()
+                .value()
+                .unwrap_or_default(),
+            output_path: conf
+                .get(path.push("output_path"))
+                .unquote()
+                .value()
+                .unwrap_or_default(),
+            item_mapping_path: conf
+                .get(path.push("item_mapping_path"))
+                .unquote()
+                .value()
+                .unwrap_or_default(),
+            session_gap_in_secs: conf
+                .get(path.push("session_gap_in_secs"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_SESSION_GAP_IN_SECS),
+            dedup_consecutive_items: conf
+                .get(path.push("dedup_consecutive_items"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_PREPROCESSING_DEDUP_CONSECUTIVE_ITEMS),
+            min_item_support: conf
+                .get(path.push("min_item_support"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_MIN_ITEM_SUPPORT),
+            min_session_length: conf
+                .get(path.push("min_session_length"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_MIN_SESSION_LENGTH),
+            max_session_length: conf
+                .get(path.push("max_session_length"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_MAX_SESSION_LENGTH),
+            remap_ids: conf
+                .get(path.push("remap_ids"))
+                .trim()
+                .value()
+                .unwrap_or(DEFAULT_REMAP_IDS),
         }
     }
 }
@@ -259,6 +552,49 @@ impl HyperparamConfig {
                 .trim()
                 .value()
                 .unwrap(),
+            popularity_weight_range: conf
+                .get(path.push("popularity_weight_range"))
+                .trim()
+                .value()
+                .map(|range: String| parse_popularity_weight_range(&range).unwrap_or_else(|err| panic!("{}", err)))
+                .unwrap_or(DEFAULT_POPULARITY_WEIGHT_RANGE),
+            neighbor_similarity_choices: conf
+                .get(path.push("neighbor_similarity_choices"))
+                .unquote()
+                .value()
+                .map(|choices: String| parse_neighbor_similarities(&choices).unwrap_or_else(|err| panic!("{}", err)))
+                .unwrap_or_else(|_| vec![NeighborSimilarity::DotProduct]),
         }
     }
 }
+
+/// Parses a range of popularity weights written as `[lowest, highest]`, e.g. `[0, 0.5]`.
+pub fn parse_popularity_weight_range(value: &str) -> Result<(f64, f64), String> {
+    let weights = value
+        .trim_matches(|c: char| c == '[' || c == ']' || c.is_whitespace())
+        .split(',')
+        .map(|weight| {
+            weight
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid popularity_weight_range: {}", value))
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+    match weights[..] {
+        [lowest, highest] if lowest <= highest => Ok((lowest, highest)),
+        _ => Err(format!("popularity_weight_range must be [lowest, highest]: {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod config_test {
+    use super::*;
+
+    #[test]
+    fn should_parse_fractional_popularity_weight_ranges() {
+        assert_eq!(Ok((0.0, 0.5)), parse_popularity_weight_range("[0, 0.5]"));
+        assert_eq!(Ok((0.0, 0.0)), parse_popularity_weight_range("[0, 0]"));
+        assert!(parse_popularity_weight_range("[0.5, 0]").is_err());
+        assert!(parse_popularity_weight_range("[0, high]").is_err());
+    }
+}
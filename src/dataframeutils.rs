@@ -2,23 +2,45 @@
 // use itertools::Itertools;
 use chrono::NaiveDateTime;
 // use tdigest::TDigest;
+use prometheus::{IntCounter, IntCounterVec};
 use rayon::prelude::*;
 use std::sync::Arc;
 
+use crate::popularity::LazyLivePopularityIndex;
+use crate::recommender::{Algorithm, Recommender};
+use crate::sessions::user_profiles::RocksDBUserProfileStore;
 use crate::sessions::RocksDBSessionStore;
+use crate::vmisknn::diversity::Diversifier;
+use crate::vmisknn::fallback::FallbackRecommender;
+use crate::vmisknn::similar_items::SimilarItems;
 use crate::vmisknn::vmis_index::VMISIndex;
 
 pub struct SharedHandlesAndConfig {
     pub session_store: Arc<RocksDBSessionStore>,
+    pub user_profile_store: Arc<RocksDBUserProfileStore>,
     pub vmis_index: Arc<VMISIndex>,
+    // The model that serves `/v1/recommend`, the VMIS-kNN index unless configured otherwise.
+    pub recommender: Arc<dyn Recommender>,
+    pub algorithm: Algorithm,
+    pub fallback_recommender: Arc<FallbackRecommender>,
+    pub popularity_index: Arc<LazyLivePopularityIndex>,
+    pub include_live_events_in_popularity: bool,
+    pub similar_items: Arc<SimilarItems>,
+    pub diversifier: Arc<Diversifier>,
     pub m_most_recent_sessions: usize,
     pub neighborhood_size_k: usize,
     pub num_items_to_recommend: usize,
     pub max_items_in_session: usize,
+    pub dedup_consecutive_items: bool,
     pub qty_workers: usize,
     pub db_compaction_ttl_in_secs: usize,
+    pub user_profile_ttl_in_secs: usize,
     pub enable_business_logic: bool,
     pub example_item_id: usize,
+    pub session_store_failures: IntCounterVec,
+    pub prediction_failures: IntCounter,
+    pub session_erasures: IntCounterVec,
+    pub user_profile_erasures: IntCounterVec,
 }
 
 pub struct TrainingDataStats {
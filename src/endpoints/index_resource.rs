@@ -70,17 +70,35 @@ pub async fn internal(config: Data<SharedHandlesAndConfig>) -> HttpResponse {
     html.push_str(&data_stats.qty_events_p100.to_string());
 
     html.push_str("<h3>Models</h3>");
-    html.push_str("hyperparameters");
+    html.push_str("algorithm: ");
+    html.push_str(&config.algorithm.to_string());
+    html.push_str("<br />hyperparameters");
     html.push_str("<br />m : ");
     html.push_str(&config.m_most_recent_sessions.to_string());
     html.push_str(" (most_recent_neighbors for evolving session)");
     html.push_str("<br />k : ");
     html.push_str(&config.neighborhood_size_k.to_string());
     html.push_str(" (top `k` closest_neighbor sessions for item scoring)");
+    html.push_str("<br />Neighbor similarity: ");
+    html.push_str(&config.vmis_index.neighbor_similarity().to_string());
     html.push_str("<br />Max items in evolving session:");
     html.push_str(&config.max_items_in_session.to_string());
+    html.push_str("<br />Deduplicate consecutive items: ");
+    html.push_str(&config.dedup_consecutive_items.to_string());
     html.push_str("<br />Qty items to recommend: ");
     html.push_str(&config.num_items_to_recommend.to_string());
+    html.push_str("<br />Fallback for sessions without recommendations: ");
+    html.push_str(&config.fallback_recommender.strategy().to_string());
+    html.push_str("<br />Diversity re-ranking: lambda=");
+    html.push_str(&config.diversifier.lambda().to_string());
+    html.push_str(", ");
+    html.push_str(&config.diversifier.similarity().to_string());
+    html.push_str(" similarity");
+    html.push_str("<br />Similar items: ");
+    html.push_str(&config.similar_items.similarity().to_string());
+    html.push_str(" similarity, precomputed for ");
+    html.push_str(&config.similar_items.qty_precomputed_items().to_string());
+    html.push_str(" items");
     html.push_str("<br /><a href=\"/v1/recommend?session_id=144&user_consent=true&item_id=");
     html.push_str(&config.example_item_id.to_string());
     html.push_str("\">v1 endpoint of our model</a>");
@@ -98,6 +116,9 @@ pub async fn internal(config: Data<SharedHandlesAndConfig>) -> HttpResponse {
     html.push_str("<br />Compaction TTL: ");
     html.push_str(&*config.db_compaction_ttl_in_secs.to_string());
     html.push_str(" seconds");
+    html.push_str("<br />User profile compaction TTL: ");
+    html.push_str(&config.user_profile_ttl_in_secs.to_string());
+    html.push_str(" seconds");
     html.push_str("<h3>Metrics</h3>");
     html.push_str("<a href=\"/internal/prometheus\">prometheus</a>");
     html.push_str("</html>");
@@ -1,2 +1,5 @@
 pub mod index_resource;
+pub mod popular_resource;
 pub mod recommend_resource;
+pub mod session_resource;
+pub mod similar_resource;
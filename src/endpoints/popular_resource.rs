@@ -0,0 +1,28 @@
+use actix_web::{get, web, HttpResponse};
+use serde::Deserialize;
+
+use crate::dataframeutils::SharedHandlesAndConfig;
+use crate::recommender::{recommend_items, RecommendOptions};
+
+#[derive(Debug, Deserialize)]
+pub struct PopularQueryParams {
+    how_many: Option<usize>,
+}
+
+// The most popular items within the configured time window, independent of the visitor session.
+#[get("/v1/popular")]
+pub async fn v1_popular(
+    data: web::Data<SharedHandlesAndConfig>,
+    query: web::Query<PopularQueryParams>,
+) -> HttpResponse {
+    let options = RecommendOptions {
+        neighborhood_size_k: data.neighborhood_size_k,
+        m_most_recent_sessions: data.m_most_recent_sessions,
+        num_items_to_recommend: query.how_many.unwrap_or(data.num_items_to_recommend),
+        enable_business_logic: data.enable_business_logic,
+        dedup_consecutive_items: data.dedup_consecutive_items,
+    };
+    let recommended_items = recommend_items(data.popularity_index.as_ref(), &[], &options);
+
+    HttpResponse::Ok().json(recommended_items)
+}
@@ -1,22 +1,31 @@
 use actix_web::{get, web, HttpResponse};
 use serde::Deserialize;
-
-use uuid::Builder;
+use std::time::SystemTime;
 
 use crate::dataframeutils::SharedHandlesAndConfig;
-use crate::vmisknn;
+use crate::sessions::evolving_session_id;
+use crate::sessions::user_profiles::{blend_user_history, user_profile_id};
+use crate::recommender::RecommendOptions;
+use crate::vmisknn::diversity::Diversifier;
+use crate::vmisknn::fallback::FallbackStrategy;
 
 #[derive(Debug, Deserialize)]
 pub struct V1QueryParams {
     item_id: u64,
     session_id: String,
     user_consent: bool,
+    user_id: Option<String>,
+    diversity_lambda: Option<f64>,
 }
 
 // Serenade's main endpoint.
 // This endpoint requires GET query parameters because the istio uses the same `session_id` query param for pod affinity.
 // This minimizes the risk that the istio uses a different session_id value from the X-header than we use on the GET request.
 // There are multiple session_id's for a visitor during a visit (jsession_id, measuring_session_id, etc).
+// The optional `user_id` links the visit to the long-term item history of a returning visitor.
+// The optional `diversity_lambda` overrides the configured relevance versus diversity trade-off, `1` disables re-ranking.
+// When VMIS-kNN finds no recommendations, the configured fallback strategy recommends instead and the
+// `X-Serenade-Fallback` response header holds the name of that strategy, e.g. `popular`.
 #[get("/v1/recommend")]
 pub async fn v1_recommend(
     data: web::Data<SharedHandlesAndConfig>,
@@ -24,42 +33,106 @@ pub async fn v1_recommend(
 ) -> HttpResponse {
     let most_recent_item = query.item_id;
     let user_consent = query.user_consent;
-    let session_id_digest = md5::compute(&query.session_id);
-    let evolving_session_id = Builder::from_bytes(session_id_digest.0).build().as_u128();
+    let evolving_session_id = evolving_session_id(&query.session_id);
 
     let vmis_index = data.vmis_index.as_ref();
     let session_store = data.session_store.as_ref();
 
-    let k = data.neighborhood_size_k;
-    let m = data.m_most_recent_sessions;
     let how_many = data.num_items_to_recommend;
     let max_items_in_session = data.max_items_in_session;
     let enable_business_logic = data.enable_business_logic;
 
+    if data.include_live_events_in_popularity {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as usize)
+            .unwrap_or(0);
+        data.popularity_index.get().add_event(most_recent_item, now);
+    }
+
     let session_items = if user_consent {
-        let mut session_items = session_store.get_session_items(&evolving_session_id);
-        if session_items.is_empty() {
-            session_items.push(most_recent_item);
-        } else if session_items.last().unwrap() != &most_recent_item {
-            session_items.push(most_recent_item);
-            if session_items.len() > max_items_in_session {
-                // Reduce the amount of session_items to max_items_in_session.
-                session_items.drain(0..1);
+        match session_store.get_session_items(&evolving_session_id) {
+            Ok(mut session_items) => {
+                if session_items.is_empty() {
+                    session_items.push(most_recent_item);
+                } else if session_items.last().unwrap() != &most_recent_item {
+                    session_items.push(most_recent_item);
+                    if session_items.len() > max_items_in_session {
+                        // Reduce the amount of session_items to max_items_in_session.
+                        session_items.drain(0..1);
+                    }
+                }
+                if let Err(err) = session_store.update_session_items(&evolving_session_id, &session_items) {
+                    // The recommendations are still valid, only the next request will miss this item.
+                    eprintln!("{}", err);
+                    data.session_store_failures.with_label_values(&["update"]).inc();
+                }
+                session_items
+            }
+            Err(err) => {
+                // Degrade to a session consisting of only the current item.
+                eprintln!("{}", err);
+                data.session_store_failures.with_label_values(&["get"]).inc();
+                vec![most_recent_item]
             }
         }
-        session_store.update_session_items(&evolving_session_id, &session_items);
-        session_items
     } else {
         vec![most_recent_item]
     };
 
-    let recommendations = vmisknn::predict(vmis_index, &session_items, k, m, how_many, enable_business_logic);
+    let session_items = match (&query.user_id, user_consent) {
+        (Some(user_id), true) => {
+            let user_profile_store = data.user_profile_store.as_ref();
+            let user_profile_id = user_profile_id(user_id);
+            let user_items = match user_profile_store.get_user_items(&user_profile_id) {
+                Ok(user_items) => user_items,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    data.session_store_failures.with_label_values(&["user_get"]).inc();
+                    Vec::new()
+                }
+            };
+            if let Err(err) = user_profile_store.add_user_item(&user_profile_id, most_recent_item) {
+                eprintln!("{}", err);
+                data.session_store_failures.with_label_values(&["user_update"]).inc();
+            }
+            blend_user_history(&session_items, &user_items, max_items_in_session)
+        }
+        _ => session_items,
+    };
 
-    let recommended_items: Vec<u64> = recommendations
-        .into_sorted_vec()
+    let diversity_lambda = query.diversity_lambda.unwrap_or_else(|| data.diversifier.lambda());
+    let options = RecommendOptions {
+        neighborhood_size_k: data.neighborhood_size_k,
+        m_most_recent_sessions: data.m_most_recent_sessions,
+        num_items_to_recommend: Diversifier::qty_candidates(how_many, diversity_lambda),
+        enable_business_logic,
+        dedup_consecutive_items: data.dedup_consecutive_items,
+    };
+    let candidates = match data.recommender.try_recommend(&session_items, &options) {
+        Ok(candidates) => candidates,
+        Err(err) => {
+            // Falls back like a session without recommendations.
+            eprintln!("{}", err);
+            data.prediction_failures.inc();
+            Vec::new()
+        }
+    };
+    let recommended_items: Vec<u64> = data
+        .diversifier
+        .rerank(vmis_index, candidates, diversity_lambda, how_many)
         .iter()
         .map(|scored| scored.id)
         .collect();
 
+    let fallback_recommender = data.fallback_recommender.as_ref();
+    if recommended_items.is_empty() && fallback_recommender.strategy() != FallbackStrategy::None {
+        // Flag the fallback in a header, clients expect a plain list of items in the body.
+        let fallback_items = fallback_recommender.recommend(vmis_index, &session_items, how_many, enable_business_logic);
+        return HttpResponse::Ok()
+            .header("X-Serenade-Fallback", fallback_recommender.strategy().to_string())
+            .json(fallback_items);
+    }
+
     HttpResponse::Ok().json(recommended_items)
 }
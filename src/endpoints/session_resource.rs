@@ -0,0 +1,103 @@
+use actix_web::{delete, post, web, HttpResponse};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::dataframeutils::SharedHandlesAndConfig;
+use crate::sessions::evolving_session_id;
+use crate::sessions::user_profiles::user_profile_id;
+
+#[derive(Debug, Deserialize)]
+pub struct BulkDeleteRequest {
+    session_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteResponse {
+    qty_deleted: usize,
+}
+
+// Erases the stored evolving session of a visitor, used to honour erasure requests and revoked consent.
+// The session_id is hashed the same way as the /v1/recommend endpoint does.
+// Deleting an unknown session is not an error, the endpoint is idempotent.
+// The long-term history of the user is kept, /v1/user/{user_id} erases it.
+#[delete("/v1/session/{session_id}")]
+pub async fn v1_delete_session(
+    data: web::Data<SharedHandlesAndConfig>,
+    session_id: web::Path<String>,
+) -> HttpResponse {
+    let evolving_session_id = evolving_session_id(&session_id);
+
+    match data.session_store.delete_session(&evolving_session_id) {
+        Ok(existed) => {
+            let outcome = if existed { "deleted" } else { "not_found" };
+            data.session_erasures.with_label_values(&[outcome]).inc();
+            HttpResponse::Ok().json(DeleteResponse {
+                qty_deleted: existed as usize,
+            })
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            data.session_erasures.with_label_values(&["failed"]).inc();
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Bulk variant of the erasure endpoint, expects a JSON body `{"session_ids": ["144", "145"]}`.
+#[post("/v1/session/bulk_delete")]
+pub async fn v1_bulk_delete_sessions(
+    data: web::Data<SharedHandlesAndConfig>,
+    request: web::Json<BulkDeleteRequest>,
+) -> HttpResponse {
+    // Repeated ids are erased and counted once.
+    let evolving_session_ids: Vec<u128> = request
+        .session_ids
+        .iter()
+        .map(|session_id| evolving_session_id(session_id))
+        .unique()
+        .collect();
+
+    match data.session_store.delete_sessions(&evolving_session_ids) {
+        Ok(qty_deleted) => {
+            let qty_not_found = evolving_session_ids.len() - qty_deleted;
+            data.session_erasures
+                .with_label_values(&["deleted"])
+                .inc_by(qty_deleted as u64);
+            data.session_erasures
+                .with_label_values(&["not_found"])
+                .inc_by(qty_not_found as u64);
+            HttpResponse::Ok().json(DeleteResponse { qty_deleted })
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            data.session_erasures
+                .with_label_values(&["failed"])
+                .inc_by(evolving_session_ids.len() as u64);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Erases the long-term item history of a user, the user_id is hashed the same way as on the /v1/recommend endpoint.
+#[delete("/v1/user/{user_id}")]
+pub async fn v1_delete_user_profile(
+    data: web::Data<SharedHandlesAndConfig>,
+    user_id: web::Path<String>,
+) -> HttpResponse {
+    let user_profile_id = user_profile_id(&user_id);
+
+    match data.user_profile_store.delete_user_profile(&user_profile_id) {
+        Ok(existed) => {
+            let outcome = if existed { "deleted" } else { "not_found" };
+            data.user_profile_erasures.with_label_values(&[outcome]).inc();
+            HttpResponse::Ok().json(DeleteResponse {
+                qty_deleted: existed as usize,
+            })
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            data.user_profile_erasures.with_label_values(&["failed"]).inc();
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use actix_web::{get, web, HttpResponse};
+use serde::Deserialize;
+
+use crate::dataframeutils::SharedHandlesAndConfig;
+
+#[derive(Debug, Deserialize)]
+pub struct SimilarQueryParams {
+    item_id: u64,
+    how_many: Option<usize>,
+}
+
+// The items most similar to `item_id`, independent of the visitor session. For product detail pages.
+#[get("/v1/similar")]
+pub async fn v1_similar(
+    data: web::Data<SharedHandlesAndConfig>,
+    query: web::Query<SimilarQueryParams>,
+) -> HttpResponse {
+    let how_many = query.how_many.unwrap_or(data.num_items_to_recommend);
+    let similar_items: Vec<u64> = data
+        .similar_items
+        .similar_items(
+            data.vmis_index.as_ref(),
+            &query.item_id,
+            how_many,
+            data.enable_business_logic,
+        )
+        .iter()
+        .map(|scored| scored.id)
+        .collect();
+
+    HttpResponse::Ok().json(similar_items)
+}
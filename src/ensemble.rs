@@ -0,0 +1,236 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+
+use crate::io::ItemId;
+use crate::recommender::{recommended_or_logged, Algorithm, RecommendOptions, Recommender};
+use crate::vmisknn::{ItemScore, PredictError};
+
+// Each member recommends this many times the requested amount, so items ranked low by one member
+// can still make it into the blend.
+const CANDIDATES_PER_RECOMMENDATION: usize = 3;
+
+/// How the scores of a member are made comparable to the scores of the other members.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScoreNormalization {
+    /// Scales the scores of a member to `[0, 1]`, the best item of a member scores `1`.
+    MinMax,
+    /// Ignores the score values, the item at rank `r` of `n` items scores `1 - r / n`.
+    Rank,
+}
+
+impl FromStr for ScoreNormalization {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "min_max" => Ok(ScoreNormalization::MinMax),
+            "rank" => Ok(ScoreNormalization::Rank),
+            _ => Err(format!("Unknown score normalization: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for ScoreNormalization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ScoreNormalization::MinMax => "min_max",
+            ScoreNormalization::Rank => "rank",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Parses ensemble members written as `algorithm:weight` pairs separated by commas,
+/// e.g. `vmis_knn:0.8,popularity:0.2`.
+pub fn parse_members(value: &str) -> Result<Vec<(Algorithm, f64)>, String> {
+    value
+        .split(',')
+        .map(|member| {
+            let (algorithm, weight) = member
+                .split_once(':')
+                .ok_or_else(|| format!("Ensemble member without weight: {}", member.trim()))?;
+            let algorithm: Algorithm = algorithm.trim().parse()?;
+            if algorithm == Algorithm::Ensemble {
+                return Err("An ensemble cannot be a member of an ensemble".to_string());
+            }
+            let weight: f64 = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid weight for ensemble member: {}", member.trim()))?;
+            Ok((algorithm, weight))
+        })
+        .collect()
+}
+
+/// Blends the normalized scores of several recommenders with fixed weights.
+pub struct Ensemble {
+    members: Vec<(Arc<dyn Recommender>, f64)>,
+    normalization: ScoreNormalization,
+}
+
+impl Ensemble {
+    pub fn new(members: Vec<(Arc<dyn Recommender>, f64)>, normalization: ScoreNormalization) -> Self {
+        Ensemble {
+            members,
+            normalization,
+        }
+    }
+}
+
+impl Recommender for Ensemble {
+    fn recommend(&self, evolving_session: &[ItemId], options: &RecommendOptions) -> Vec<ItemScore> {
+        recommended_or_logged(self.try_recommend(evolving_session, options))
+    }
+
+    /// Fails when any member fails.
+    fn try_recommend(
+        &self,
+        evolving_session: &[ItemId],
+        options: &RecommendOptions,
+    ) -> Result<Vec<ItemScore>, PredictError> {
+        let member_options = RecommendOptions {
+            num_items_to_recommend: options.num_items_to_recommend * CANDIDATES_PER_RECOMMENDATION,
+            ..options.clone()
+        };
+        let mut blended_scores: HashMap<ItemId, f64> = HashMap::new();
+        for (member, weight) in self.members.iter() {
+            if *weight == 0.0 {
+                continue;
+            }
+            let scored_items = member.try_recommend(evolving_session, &member_options)?;
+            let normalized_scores = normalize(&scored_items, self.normalization);
+            for (scored, normalized_score) in scored_items.iter().zip(normalized_scores) {
+                *blended_scores.entry(scored.id).or_insert(0.0) += weight * normalized_score;
+            }
+        }
+
+        let mut recommendations: Vec<ItemScore> = blended_scores
+            .into_iter()
+            .map(|(item_id, score)| ItemScore::new(item_id, score))
+            .collect();
+        recommendations.sort_unstable_by(|a, b| {
+            b.score.partial_cmp(&a.score).unwrap().then(a.id.cmp(&b.id))
+        });
+        recommendations.truncate(options.num_items_to_recommend);
+        Ok(recommendations)
+    }
+}
+
+// Normalizes the scores of items ordered by descending score.
+fn normalize(scored_items: &[ItemScore], normalization: ScoreNormalization) -> Vec<f64> {
+    match normalization {
+        ScoreNormalization::MinMax => {
+            let max_score = scored_items.iter().map(|scored| scored.score).fold(f64::MIN, f64::max);
+            let min_score = scored_items.iter().map(|scored| scored.score).fold(f64::MAX, f64::min);
+            let range = max_score - min_score;
+            scored_items
+                .iter()
+                .map(|scored| {
+                    if range > 0.0 {
+                        (scored.score - min_score) / range
+                    } else {
+                        1.0
+                    }
+                })
+                .collect()
+        }
+        ScoreNormalization::Rank => {
+            let qty_items = scored_items.len() as f64;
+            (0..scored_items.len())
+                .map(|rank| 1.0 - rank as f64 / qty_items)
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod ensemble_test {
+    use super::*;
+    use crate::recommender::recommend_items;
+
+    struct FixedScores(Vec<(ItemId, f64)>);
+
+    impl Recommender for FixedScores {
+        fn recommend(&self, _evolving_session: &[ItemId], options: &RecommendOptions) -> Vec<ItemScore> {
+            self.0
+                .iter()
+                .take(options.num_items_to_recommend)
+                .map(|(item_id, score)| ItemScore::new(*item_id, *score))
+                .collect()
+        }
+    }
+
+    fn options(num_items_to_recommend: usize) -> RecommendOptions {
+        RecommendOptions {
+            neighborhood_size_k: 500,
+            m_most_recent_sessions: 500,
+            num_items_to_recommend,
+            enable_business_logic: false,
+            dedup_consecutive_items: false,
+        }
+    }
+
+    fn members(weight_a: f64, weight_b: f64) -> Vec<(Arc<dyn Recommender>, f64)> {
+        vec![
+            (Arc::new(FixedScores(vec![(1, 10.0), (2, 5.0), (3, 0.0)])), weight_a),
+            (Arc::new(FixedScores(vec![(3, 900.0), (2, 800.0), (4, 100.0)])), weight_b),
+        ]
+    }
+
+    #[test]
+    fn should_blend_min_max_normalized_scores() {
+        let undertest = Ensemble::new(members(1.0, 1.0), ScoreNormalization::MinMax);
+        let recommendations = undertest.recommend(&[], &options(10));
+        // 2: 0.5 + 0.875, 3: 0.0 + 1.0, 1: 1.0 + 0.0, 4: 0.0
+        assert_eq!(vec![2, 1, 3, 4], recommendations.iter().map(|scored| scored.id).collect::<Vec<_>>());
+        assert_eq!(1.375, recommendations[0].score);
+    }
+
+    #[test]
+    fn should_blend_rank_normalized_scores() {
+        let undertest = Ensemble::new(members(1.0, 2.0), ScoreNormalization::Rank);
+        // 3: 1/3 + 2, 2: 2/3 + 4/3, 1: 1, 4: 2/3
+        assert_eq!(vec![3, 2], recommend_items(&undertest, &[], &options(2)));
+    }
+
+    #[test]
+    fn should_ignore_members_without_weight() {
+        let undertest = Ensemble::new(members(1.0, 0.0), ScoreNormalization::MinMax);
+        assert_eq!(vec![1, 2, 3], recommend_items(&undertest, &[], &options(10)));
+    }
+
+    #[test]
+    fn should_report_failing_members() {
+        struct Failing;
+        impl Recommender for Failing {
+            fn recommend(&self, _evolving_session: &[ItemId], _options: &RecommendOptions) -> Vec<ItemScore> {
+                Vec::new()
+            }
+
+            fn try_recommend(
+                &self,
+                _evolving_session: &[ItemId],
+                _options: &RecommendOptions,
+            ) -> Result<Vec<ItemScore>, PredictError> {
+                Err(PredictError::EmptySession)
+            }
+        }
+        let mut failing_members = members(1.0, 1.0);
+        failing_members.push((Arc::new(Failing), 1.0));
+        let undertest = Ensemble::new(failing_members, ScoreNormalization::MinMax);
+        assert_eq!(Some(PredictError::EmptySession), undertest.try_recommend(&[], &options(10)).err());
+        assert!(undertest.recommend(&[], &options(10)).is_empty());
+    }
+
+    #[test]
+    fn should_parse_members() {
+        let parsed = parse_members("vmis_knn:0.8, popularity:0.2").unwrap();
+        assert_eq!(vec![(Algorithm::VMISkNN, 0.8), (Algorithm::Popularity, 0.2)], parsed);
+        assert!(parse_members("vmis_knn").is_err());
+        assert!(parse_members("vmis_knn:heavy").is_err());
+        assert!(parse_members("ensemble:1").is_err());
+    }
+}
@@ -3,7 +3,7 @@ use itertools::Itertools;
 use rayon::iter::ParallelBridge;
 use rayon::prelude::ParallelIterator;
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, BufWriter, Write};
 use std::path::Path;
 
 pub type TrainingSessionId = u32;
@@ -29,6 +29,16 @@ pub fn read_training_data(training_data_path: &str) -> Vec<(TrainingSessionId, I
     training_data.collect()
 }
 
+/// Writes interactions in the format that `read_training_data` reads.
+pub fn write_training_data(path: &str, interactions: &[(TrainingSessionId, ItemId, Time)]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "SessionId\tItemId\tTime")?;
+    for (session_id, item_id, time) in interactions.iter() {
+        writeln!(writer, "{}\t{}\t{}.0", session_id, item_id, time)?;
+    }
+    writer.flush()
+}
+
 fn create_buffered_line_reader<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
 where
     P: AsRef<Path>,
@@ -57,3 +67,125 @@ pub fn read_test_data_evolving(test_data_file: &str) -> HashMap<TrainingSessionI
 
     evolving_sessions
 }
+
+/// Identifies a test event by its test session and the amount of session items the prediction is based on.
+pub type EventKey = (TrainingSessionId, usize);
+
+/// The recommendations and the remaining items of a test event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prediction {
+    /// Absent in predictions files without event keys, e.g. those written by other tools.
+    pub key: Option<EventKey>,
+    pub recommendations: Vec<ItemId>,
+    pub next_items: Vec<ItemId>,
+}
+
+/// Reads predictions as written by `write_predictions`, one test event per line with the
+/// comma separated recommendations and the comma separated next items, separated by a `;`.
+/// A line can start with the event key as `session_id,position;`. Every test event needs at
+/// least one next item.
+pub fn read_predictions(predictions_file: &str) -> io::Result<Vec<Prediction>> {
+    let invalid_data = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let parse_items = |items: &str| -> io::Result<Vec<ItemId>> {
+        items
+            .split(',')
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                item.trim()
+                    .parse::<ItemId>()
+                    .map_err(|err| invalid_data(format!("{}: {}", item, err)))
+            })
+            .collect()
+    };
+    let parse_key = |key: &str| -> Option<EventKey> {
+        let (session_id, position) = key.split_once(',')?;
+        Some((session_id.trim().parse().ok()?, position.trim().parse().ok()?))
+    };
+    let mut predictions = Vec::new();
+    for line in create_buffered_line_reader(predictions_file)? {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let (key, recommendations, next_items) = match line.split(';').collect_vec()[..] {
+            [recommendations, next_items] => (None, recommendations, next_items),
+            [key, recommendations, next_items] => {
+                let key = parse_key(key).ok_or_else(|| invalid_data(format!("Line with an invalid event key: {}", line)))?;
+                (Some(key), recommendations, next_items)
+            }
+            _ => return Err(invalid_data(format!("Line without next items: {}", line))),
+        };
+        let next_items = parse_items(next_items)?;
+        if next_items.is_empty() {
+            return Err(invalid_data(format!("Line with an empty list of next items: {}", line)));
+        }
+        predictions.push(Prediction {
+            key,
+            recommendations: parse_items(recommendations)?,
+            next_items,
+        });
+    }
+    Ok(predictions)
+}
+
+pub fn write_predictions(predictions_file: &str, predictions: &[Prediction]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(predictions_file)?);
+    for prediction in predictions.iter() {
+        if let Some((session_id, position)) = prediction.key {
+            write!(writer, "{},{};", session_id, position)?;
+        }
+        writeln!(
+            writer,
+            "{};{}",
+            prediction.recommendations.iter().join(","),
+            prediction.next_items.iter().join(",")
+        )?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod io_test {
+    use super::*;
+
+    fn predictions_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("serenade_{}_{}.txt", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn should_read_the_written_predictions() {
+        let path = predictions_path("keyed_predictions");
+        let predictions = vec![
+            Prediction {
+                key: Some((7, 1)),
+                recommendations: vec![1, 2],
+                next_items: vec![2, 3],
+            },
+            Prediction {
+                key: Some((7, 2)),
+                recommendations: vec![],
+                next_items: vec![3],
+            },
+        ];
+        write_predictions(&path, &predictions).unwrap();
+        assert_eq!(predictions, read_predictions(&path).unwrap());
+
+        // predictions of other tools have no event keys
+        std::fs::write(&path, "1,2;2,3\n").unwrap();
+        let unkeyed = read_predictions(&path).unwrap();
+        assert_eq!(None, unkeyed[0].key);
+        assert_eq!(vec![2, 3], unkeyed[0].next_items);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_reject_predictions_without_next_items() {
+        let path = predictions_path("predictions_without_next_items");
+        std::fs::write(&path, "1,2;2,3\n1,2;\n").unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, read_predictions(&path).unwrap_err().kind());
+        std::fs::write(&path, "7,1;1,2;\n").unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, read_predictions(&path).unwrap_err().kind());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
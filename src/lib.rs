@@ -2,10 +2,16 @@ pub mod config;
 pub mod config_processors;
 pub mod dataframeutils;
 pub mod endpoints;
+pub mod ensemble;
 pub mod hyperparameter;
 pub mod io;
 pub mod metrics;
+pub mod popularity;
+pub mod preprocessing;
+pub mod recommender;
+pub mod rules;
 pub mod sessions;
+pub mod split;
 pub mod stopwatch;
 pub mod vmisknn;
 pub mod objective;
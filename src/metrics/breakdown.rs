@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::metrics::evaluation_reporter::EvaluationProtocol;
+use crate::metrics::hitrate::HitRate;
+use crate::metrics::mrr::Mrr;
+use crate::metrics::ndcg::Ndcg;
+use crate::metrics::report::{MetricValue, LATENCY_PERCENTILES};
+use crate::metrics::SessionMetric;
+use crate::stopwatch::Stopwatch;
+
+/// Upper bounds of the buckets for the position in the session, the amount of items seen before the prediction.
+const POSITION_BOUNDS: [usize; 5] = [1, 2, 3, 5, 10];
+/// Upper bounds of the buckets for the length of the test session. Sessions with one item are not evaluated.
+const SESSION_LENGTH_BOUNDS: [usize; 5] = [2, 3, 5, 10, 20];
+
+/// The index of the bucket of `value`, the last bucket holds everything above the last bound.
+fn bucket_index(value: usize, bounds: &[usize]) -> usize {
+    bounds
+        .iter()
+        .position(|bound| value <= *bound)
+        .unwrap_or(bounds.len())
+}
+
+/// Labels like `1`, `4-5` and `11+` for the buckets of values starting at `min`.
+fn bucket_labels(min: usize, bounds: &[usize]) -> Vec<String> {
+    let mut labels = Vec::with_capacity(bounds.len() + 1);
+    let mut lower = min;
+    for bound in bounds.iter() {
+        if lower == *bound {
+            labels.push(bound.to_string());
+        } else {
+            labels.push(format!("{}-{}", lower, bound));
+        }
+        lower = bound + 1;
+    }
+    labels.push(format!("{}+", lower));
+    labels
+}
+
+/// The accuracy metrics of one bucket of a breakdown.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BreakdownValue {
+    /// What the test events are grouped by, `position` or `session_length`.
+    pub breakdown: String,
+    pub bucket: String,
+    pub qty: usize,
+    pub metrics: Vec<MetricValue>,
+}
+
+/// The latency percentiles of the predictions at one bucket of positions in the session.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencyBucket {
+    pub bucket: String,
+    pub qty: usize,
+    pub latency_in_micros: BTreeMap<String, f64>,
+}
+
+#[derive(Clone)]
+struct BucketMetrics {
+    qty: usize,
+    mrr: Mrr,
+    hitrate: HitRate,
+    ndcg: Ndcg,
+}
+
+impl BucketMetrics {
+    fn new(length: usize) -> BucketMetrics {
+        BucketMetrics {
+            qty: 0,
+            mrr: Mrr::new(length),
+            hitrate: HitRate::new(length),
+            ndcg: Ndcg::new(length),
+        }
+    }
+
+    fn add(&mut self, recommendations: &[u64], next_items: &[u64]) {
+        self.qty += 1;
+        self.mrr.add(recommendations, next_items);
+        self.hitrate.add(recommendations, next_items);
+        self.ndcg.add(recommendations, next_items);
+    }
+
+    fn merge(&mut self, other: BucketMetrics) {
+        self.qty += other.qty;
+        self.mrr.merge(other.mrr);
+        self.hitrate.merge(other.hitrate);
+        self.ndcg.merge(other.ndcg);
+    }
+
+    fn metric_values(&self) -> Vec<MetricValue> {
+        let metrics: [&dyn SessionMetric; 3] = [&self.mrr, &self.hitrate, &self.ndcg];
+        metrics
+            .iter()
+            .map(|metric| MetricValue::new(metric.get_name(), metric.result()))
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+struct Breakdown {
+    name: &'static str,
+    labels: Vec<String>,
+    bounds: &'static [usize],
+    buckets: Vec<BucketMetrics>,
+}
+
+impl Breakdown {
+    fn new(name: &'static str, min: usize, bounds: &'static [usize], length: usize) -> Breakdown {
+        Breakdown {
+            name,
+            labels: bucket_labels(min, bounds),
+            bounds,
+            buckets: vec![BucketMetrics::new(length); bounds.len() + 1],
+        }
+    }
+
+    fn add(&mut self, value: usize, recommendations: &[u64], next_items: &[u64]) {
+        self.buckets[bucket_index(value, self.bounds)].add(recommendations, next_items);
+    }
+
+    fn merge(&mut self, other: Breakdown) {
+        for (bucket, other_bucket) in self.buckets.iter_mut().zip(other.buckets) {
+            bucket.merge(other_bucket);
+        }
+    }
+
+    fn values(&self) -> Vec<BreakdownValue> {
+        self.labels
+            .iter()
+            .zip(self.buckets.iter())
+            .filter(|(_, bucket)| bucket.qty > 0)
+            .map(|(label, bucket)| BreakdownValue {
+                breakdown: self.name.to_string(),
+                bucket: label.clone(),
+                qty: bucket.qty,
+                metrics: bucket.metric_values(),
+            })
+            .collect()
+    }
+}
+
+/// MRR, HitRate and NDCG per position in the session and per session length, to see which
+/// predictions are hard, like the first click or long sessions. Mergeable like `EvaluationReporter`.
+#[derive(Clone)]
+pub struct BreakdownReporter {
+    protocol: EvaluationProtocol,
+    by_position: Breakdown,
+    by_session_length: Breakdown,
+}
+
+impl BreakdownReporter {
+    pub fn new(length: usize) -> BreakdownReporter {
+        BreakdownReporter {
+            protocol: EvaluationProtocol::default(),
+            by_position: Breakdown::new("position", 1, &POSITION_BOUNDS, length),
+            by_session_length: Breakdown::new("session_length", 2, &SESSION_LENGTH_BOUNDS, length),
+        }
+    }
+
+    pub fn with_protocol(self, protocol: EvaluationProtocol) -> Self {
+        BreakdownReporter { protocol, ..self }
+    }
+
+    /// Adds the prediction after the first `position` items of a test session with `session_length` items.
+    pub fn add(&mut self, position: usize, session_length: usize, recommendations: &[u64], next_items: &[u64]) {
+        let next_items = self.protocol.target_items(next_items);
+        self.by_position.add(position, recommendations, next_items);
+        self.by_session_length.add(session_length, recommendations, next_items);
+    }
+
+    pub fn merge(&mut self, other: BreakdownReporter) {
+        self.by_position.merge(other.by_position);
+        self.by_session_length.merge(other.by_session_length);
+    }
+
+    /// The metrics of every non-empty bucket, by position first.
+    pub fn values(&self) -> Vec<BreakdownValue> {
+        let mut values = self.by_position.values();
+        values.extend(self.by_session_length.values());
+        values
+    }
+
+    /// The breakdowns as a csv table with one row per bucket.
+    pub fn result_table(&self) -> String {
+        let values = self.values();
+        let mut rows = Vec::with_capacity(values.len() + 1);
+        if let Some(value) = values.first() {
+            let names = value.metrics.iter().map(|metric_value| &metric_value.name).join(",");
+            rows.push(format!("breakdown,bucket,qty,{}", names));
+        }
+        for value in values.iter() {
+            let scores = value
+                .metrics
+                .iter()
+                .map(|metric_value| format!("{:.4}", metric_value.value))
+                .join(",");
+            rows.push(format!("{},{},{},{}", value.breakdown, value.bucket, value.qty, scores));
+        }
+        rows.join("\n")
+    }
+}
+
+/// The latency percentiles per bucket of positions in the session, for the non-empty buckets.
+pub fn latency_by_position(stopwatch: &Stopwatch) -> Vec<LatencyBucket> {
+    bucket_labels(1, &POSITION_BOUNDS)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, label)| {
+            let bucket = stopwatch.filter_positions(|position| bucket_index(position as usize, &POSITION_BOUNDS) == index);
+            if bucket.get_n() == 0 {
+                return None;
+            }
+            let latency_in_micros = LATENCY_PERCENTILES
+                .iter()
+                .map(|percentile| (format!("p{}", percentile), bucket.get_percentile_in_micros(*percentile)))
+                .collect();
+            Some(LatencyBucket {
+                bucket: label,
+                qty: bucket.get_n(),
+                latency_in_micros,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod breakdown_test {
+    use super::*;
+
+    #[test]
+    fn should_label_buckets() {
+        assert_eq!(vec!["1", "2", "3", "4-5", "6-10", "11+"], bucket_labels(1, &POSITION_BOUNDS));
+        assert_eq!(vec!["2", "3", "4-5", "6-10", "11-20", "21+"], bucket_labels(2, &SESSION_LENGTH_BOUNDS));
+        assert_eq!(0, bucket_index(1, &POSITION_BOUNDS));
+        assert_eq!(3, bucket_index(5, &POSITION_BOUNDS));
+        assert_eq!(5, bucket_index(42, &POSITION_BOUNDS));
+    }
+
+    #[test]
+    fn should_break_down_by_position_and_session_length() {
+        let mut undertest = BreakdownReporter::new(20);
+        undertest.add(1, 3, &[2, 1], &[1, 3]);
+        let mut other = BreakdownReporter::new(20);
+        other.add(2, 3, &[3], &[3]);
+        undertest.merge(other);
+
+        let values = undertest.values();
+        let summary = values
+            .iter()
+            .map(|value| (value.breakdown.as_str(), value.bucket.as_str(), value.qty, value.metrics[0].value))
+            .collect_vec();
+        assert_eq!(
+            vec![("position", "1", 1, 0.5), ("position", "2", 1, 1.0), ("session_length", "3", 2, 0.75)],
+            summary
+        );
+        assert_eq!("breakdown,bucket,qty,Mrr@20,HitRate@20,Ndcg@20", undertest.result_table().lines().next().unwrap());
+    }
+
+    #[test]
+    fn should_report_latency_by_position() {
+        let mut stopwatch = Stopwatch::new();
+        for position in [1, 1, 4] {
+            stopwatch.start();
+            stopwatch.stop(&position);
+        }
+        let latency = latency_by_position(&stopwatch);
+        let buckets = latency.iter().map(|bucket| (bucket.bucket.as_str(), bucket.qty)).collect_vec();
+        assert_eq!(vec![("1", 2), ("4-5", 1)], buckets);
+        assert!(latency[0].latency_in_micros.contains_key("p50"));
+    }
+}
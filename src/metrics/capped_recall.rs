@@ -0,0 +1,85 @@
+use crate::metrics::SessionMetric;
+use std::collections::HashSet;
+
+/// Recall normalized by `min(k, |next_items|)` instead of `|next_items|`, so a session with more
+/// next items than recommendations can still reach `1`.
+#[derive(Clone)]
+pub struct CappedRecall {
+    sum_of_scores: f64,
+    qty: usize,
+    length: usize,
+}
+
+impl CappedRecall {
+    pub fn new(length: usize) -> CappedRecall {
+        CappedRecall {
+            sum_of_scores: 0_f64,
+            qty: 0,
+            length,
+        }
+    }
+}
+
+impl SessionMetric for CappedRecall {
+    fn add(&mut self, recommendations: &[u64], next_items: &[u64]) {
+        self.qty += 1;
+        let top_recos: HashSet<&u64> = recommendations.iter().take(self.length).collect();
+        let unique_next_items: HashSet<&u64> = next_items.iter().collect();
+        let max_hits = unique_next_items.len().min(self.length);
+        if max_hits > 0 {
+            let qty_hits = top_recos.intersection(&unique_next_items).count();
+            self.sum_of_scores += qty_hits as f64 / max_hits as f64
+        }
+    }
+
+    fn result(&self) -> f64 {
+        if self.qty > 0 {
+            self.sum_of_scores / self.qty as f64
+        } else {
+            0.0
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.sum_of_scores += other.sum_of_scores;
+        self.qty += other.qty;
+    }
+
+    fn get_name(&self) -> String {
+        format!("CappedRecall@{}", self.length)
+    }
+}
+
+#[cfg(test)]
+mod capped_recall_test {
+    use super::*;
+
+    #[test]
+    fn should_calculate_capped_recall() {
+        let mut mymetric = CappedRecall::new(20);
+        let recommendations: Vec<u64> = vec![
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        ];
+        let actual_next_items: Vec<u64> = vec![3, 55, 3, 4];
+        mymetric.add(&recommendations, &actual_next_items);
+        assert!((2.0 / 3.0 - mymetric.result()).abs() < f64::EPSILON);
+        assert_eq!("CappedRecall@20", mymetric.get_name());
+    }
+
+    #[test]
+    fn should_cap_by_the_length() {
+        let mut mymetric = CappedRecall::new(2);
+        mymetric.add(&[1, 2, 3], &[1, 2, 3]);
+        assert!((1.0 - mymetric.result()).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn should_merge_capped_recall() {
+        let mut mymetric = CappedRecall::new(20);
+        mymetric.add(&[1, 2], &[2]);
+        let mut other = CappedRecall::new(20);
+        other.add(&[1, 2], &[3]);
+        mymetric.merge(other);
+        assert_eq!(0.5, mymetric.result());
+    }
+}
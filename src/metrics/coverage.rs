@@ -1,10 +1,11 @@
 use crate::io::{ItemId, Time, TrainingSessionId};
-use crate::metrics::SessionMetric;
+use crate::metrics::{SessionMetric, TrainingItems};
 
 use hashbrown::HashSet;
 use itertools::Itertools;
 use std::cmp;
 
+#[derive(Clone)]
 pub struct Coverage {
     unique_training_items: usize,
     test_items: HashSet<u64>,
@@ -15,11 +16,12 @@ impl Coverage {}
 
 impl Coverage {
     pub fn new(training_df: &[(TrainingSessionId, ItemId, Time)], length: usize) -> Coverage {
-        let mut distinct_item_ids = training_df.iter().map(|record| record.1).collect_vec();
-        distinct_item_ids.sort_unstable();
-        distinct_item_ids.dedup();
+        Coverage::with_training_items(&TrainingItems::new(training_df), length)
+    }
+
+    pub fn with_training_items(training_items: &TrainingItems, length: usize) -> Coverage {
         Coverage {
-            unique_training_items: distinct_item_ids.len(),
+            unique_training_items: training_items.catalog.len(),
             test_items: HashSet::new(),
             length,
         }
@@ -45,7 +47,28 @@ impl SessionMetric for Coverage {
         }
     }
 
+    fn merge(&mut self, other: Self) {
+        self.test_items.extend(other.test_items);
+    }
+
     fn get_name(&self) -> String {
         format!("Coverage@{}", self.length)
     }
 }
+
+#[cfg(test)]
+mod coverage_test {
+    use super::*;
+
+    #[test]
+    fn should_merge_coverage() {
+        let training_df = vec![(1, 1, 0), (1, 2, 0), (2, 3, 0), (2, 4, 0)];
+        let mut mymetric = Coverage::new(&training_df, 20);
+        mymetric.add(&[1, 2], &[]);
+        let mut other = Coverage::new(&training_df, 20);
+        other.add(&[2, 3], &[]);
+        mymetric.merge(other);
+        // the items recommended by both accumulators are counted once
+        assert_eq!(0.75, mymetric.result());
+    }
+}
@@ -1,16 +1,95 @@
 use crate::io::{ItemId, Time, TrainingSessionId};
+use crate::metrics::capped_recall::CappedRecall;
 use crate::metrics::coverage::Coverage;
 use crate::metrics::f1score::F1score;
+use crate::metrics::gini::Gini;
 use crate::metrics::hitrate::HitRate;
+use crate::metrics::intra_list_diversity::IntraListDiversity;
+use crate::metrics::map::Map;
 use crate::metrics::mrr::Mrr;
 use crate::metrics::ndcg::Ndcg;
+use crate::metrics::novelty::Novelty;
 use crate::metrics::popularity::Popularity;
 use crate::metrics::precision::Precision;
 use crate::metrics::recall::Recall;
-use crate::metrics::SessionMetric;
+use crate::metrics::report::MetricValue;
+use crate::metrics::serendipity::Serendipity;
+use crate::metrics::{SessionMetric, TrainingItems};
 
-pub struct EvaluationReporter {
-    n: i32,
+use itertools::Itertools;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Which of the remaining items of a test session are the targets of a prediction. `Mrr` and `HitRate`
+/// only score the first target, `Ndcg`, `Precision`, `Recall`, `CappedRecall`, `Map` and `F1score`
+/// score all targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvaluationProtocol {
+    /// Only the next item, the strict next-item prediction task.
+    NextItem,
+    /// All remaining items of the session, the default.
+    #[default]
+    RemainingItems,
+    /// At most the next `n` items.
+    NextItems(usize),
+}
+
+impl EvaluationProtocol {
+    /// The target items among the `remaining_items` of the session.
+    pub fn target_items<'a>(&self, remaining_items: &'a [u64]) -> &'a [u64] {
+        let qty_targets = match self {
+            EvaluationProtocol::NextItem => 1,
+            EvaluationProtocol::RemainingItems => remaining_items.len(),
+            EvaluationProtocol::NextItems(n) => *n,
+        };
+        &remaining_items[..qty_targets.min(remaining_items.len())]
+    }
+}
+
+impl FromStr for EvaluationProtocol {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "next_item" => Ok(EvaluationProtocol::NextItem),
+            "remaining_items" => Ok(EvaluationProtocol::RemainingItems),
+            _ => match value.strip_prefix("next_items:").map(str::parse::<usize>) {
+                Some(Ok(n)) if n > 0 => Ok(EvaluationProtocol::NextItems(n)),
+                _ => Err(format!("Unknown evaluation protocol: {}", value)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for EvaluationProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvaluationProtocol::NextItem => write!(f, "next_item"),
+            EvaluationProtocol::RemainingItems => write!(f, "remaining_items"),
+            EvaluationProtocol::NextItems(n) => write!(f, "next_items:{}", n),
+        }
+    }
+}
+
+/// Parses a comma separated list of cutoffs like `1,5,10,20`, sorted ascending without duplicates.
+pub fn parse_cutoffs(value: &str) -> Result<Vec<usize>, String> {
+    let mut cutoffs = value
+        .split(',')
+        .map(|cutoff| match cutoff.trim().parse::<usize>() {
+            Ok(cutoff) if cutoff > 0 => Ok(cutoff),
+            _ => Err(format!("Invalid cutoff: {}", cutoff.trim())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    cutoffs.sort_unstable();
+    cutoffs.dedup();
+    Ok(cutoffs)
+}
+
+/// All metrics at one cutoff.
+#[derive(Clone)]
+struct CutoffMetrics {
+    cutoff: usize,
     mrr: Mrr,
     ndcg: Ndcg,
     hitrate: HitRate,
@@ -18,45 +97,37 @@ pub struct EvaluationReporter {
     precision: Precision,
     coverage: Coverage,
     recall: Recall,
+    capped_recall: CappedRecall,
+    map: Map,
     f1: F1score,
+    ild: IntraListDiversity,
+    novelty: Novelty,
+    serendipity: Serendipity,
+    gini: Gini,
 }
 
-impl EvaluationReporter {}
-
-impl EvaluationReporter {
-    pub fn new(
-        training_df: &[(TrainingSessionId, ItemId, Time)],
-        length: usize,
-    ) -> EvaluationReporter {
-        // If we define these metrics as mutable as we expect it to be,
-        // the compilation reports a warning that these variables dont need to be mutable.
-        let n = 0;
-        let mrr = Mrr::new(length);
-        let ndcg = Ndcg::new(length);
-        let hitrate = HitRate::new(length);
-        let popularity = Popularity::new(training_df, length);
-        let precision = Precision::new(length);
-        let coverage = Coverage::new(training_df, length);
-        let recall = Recall::new(length);
-        let f1 = F1score::new(length);
-
-        EvaluationReporter {
-            n,
-            mrr,
-            ndcg,
-            hitrate,
-            popularity,
-            precision,
-            coverage,
-            recall,
-            f1,
+impl CutoffMetrics {
+    fn new(training_items: &Arc<TrainingItems>, cutoff: usize) -> CutoffMetrics {
+        CutoffMetrics {
+            cutoff,
+            mrr: Mrr::new(cutoff),
+            ndcg: Ndcg::new(cutoff),
+            hitrate: HitRate::new(cutoff),
+            popularity: Popularity::with_training_items(training_items.clone(), cutoff),
+            precision: Precision::new(cutoff),
+            coverage: Coverage::with_training_items(training_items, cutoff),
+            recall: Recall::new(cutoff),
+            capped_recall: CappedRecall::new(cutoff),
+            map: Map::new(cutoff),
+            f1: F1score::new(cutoff),
+            ild: IntraListDiversity::with_training_items(training_items.clone(), cutoff),
+            novelty: Novelty::with_training_items(training_items.clone(), cutoff),
+            serendipity: Serendipity::with_training_items(training_items, cutoff),
+            gini: Gini::with_training_items(training_items.clone(), cutoff),
         }
     }
-}
 
-impl EvaluationReporter {
-    pub fn add(&mut self, recommendations: &[u64], next_items: &[u64]) {
-        self.n += 1;
+    fn add(&mut self, recommendations: &[u64], next_items: &[u64]) {
         self.mrr.add(recommendations, next_items);
         self.ndcg.add(recommendations, next_items);
         self.hitrate.add(recommendations, next_items);
@@ -64,54 +135,245 @@ impl EvaluationReporter {
         self.precision.add(recommendations, next_items);
         self.coverage.add(recommendations, next_items);
         self.recall.add(recommendations, next_items);
+        self.capped_recall.add(recommendations, next_items);
+        self.map.add(recommendations, next_items);
         self.f1.add(recommendations, next_items);
+        self.ild.add(recommendations, next_items);
+        self.novelty.add(recommendations, next_items);
+        self.serendipity.add(recommendations, next_items);
+        self.gini.add(recommendations, next_items);
+    }
+
+    fn metric_values(&self) -> Vec<MetricValue> {
+        let metrics: [&dyn SessionMetric; 14] = [
+            &self.mrr,
+            &self.ndcg,
+            &self.hitrate,
+            &self.popularity,
+            &self.precision,
+            &self.coverage,
+            &self.recall,
+            &self.capped_recall,
+            &self.map,
+            &self.f1,
+            &self.ild,
+            &self.novelty,
+            &self.serendipity,
+            &self.gini,
+        ];
+        metrics
+            .iter()
+            .map(|metric| MetricValue::new(metric.get_name(), metric.result()))
+            .collect()
+    }
+
+    fn merge(&mut self, other: CutoffMetrics) {
+        self.mrr.merge(other.mrr);
+        self.ndcg.merge(other.ndcg);
+        self.hitrate.merge(other.hitrate);
+        self.popularity.merge(other.popularity);
+        self.precision.merge(other.precision);
+        self.coverage.merge(other.coverage);
+        self.recall.merge(other.recall);
+        self.capped_recall.merge(other.capped_recall);
+        self.map.merge(other.map);
+        self.f1.merge(other.f1);
+        self.ild.merge(other.ild);
+        self.novelty.merge(other.novelty);
+        self.serendipity.merge(other.serendipity);
+        self.gini.merge(other.gini);
+    }
+}
+
+/// All metrics of an evaluation, at every cutoff in one pass over the recommendations. Clones start
+/// from the same state and can be merged again, so disjoint parts of a test set can be evaluated in parallel.
+#[derive(Clone)]
+pub struct EvaluationReporter {
+    n: i32,
+    protocol: EvaluationProtocol,
+    cutoffs: Vec<CutoffMetrics>,
+}
+
+impl EvaluationReporter {
+    pub fn new(training_df: &[(TrainingSessionId, ItemId, Time)], length: usize) -> EvaluationReporter {
+        EvaluationReporter::with_cutoffs(training_df, &[length])
+    }
+
+    /// Evaluates the top `cutoff` recommendations for each of the `cutoffs`.
+    pub fn with_cutoffs(training_df: &[(TrainingSessionId, ItemId, Time)], cutoffs: &[usize]) -> EvaluationReporter {
+        let training_items = Arc::new(TrainingItems::new(training_df));
+        EvaluationReporter {
+            n: 0,
+            protocol: EvaluationProtocol::default(),
+            cutoffs: cutoffs
+                .iter()
+                .map(|cutoff| CutoffMetrics::new(&training_items, *cutoff))
+                .collect(),
+        }
+    }
+
+    pub fn with_protocol(self, protocol: EvaluationProtocol) -> Self {
+        EvaluationReporter { protocol, ..self }
+    }
+
+    pub fn protocol(&self) -> EvaluationProtocol {
+        self.protocol
+    }
+}
+
+impl EvaluationReporter {
+    /// Adds a prediction, `next_items` are all remaining items of the session and the protocol picks the targets.
+    pub fn add(&mut self, recommendations: &[u64], next_items: &[u64]) {
+        self.n += 1;
+        let next_items = self.protocol.target_items(next_items);
+        for cutoff_metrics in self.cutoffs.iter_mut() {
+            cutoff_metrics.add(recommendations, next_items);
+        }
+    }
+
+    /// The result of every metric at every cutoff, in the order of `get_name`.
+    pub fn metric_values(&self) -> Vec<MetricValue> {
+        self.cutoffs
+            .iter()
+            .flat_map(|cutoff_metrics| cutoff_metrics.metric_values())
+            .collect()
+    }
+
+    pub fn qty_evaluations(&self) -> usize {
+        self.n as usize
+    }
+
+    pub fn merge(&mut self, other: EvaluationReporter) {
+        self.n += other.n;
+        for (cutoff_metrics, other_cutoff_metrics) in self.cutoffs.iter_mut().zip(other.cutoffs) {
+            cutoff_metrics.merge(other_cutoff_metrics);
+        }
     }
 
     pub fn result(&self) -> String {
-        let n = format!("{}", self.n);
-        let mrr_score = format!("{:.4}", self.mrr.result());
-        let ndcg_score = format!("{:.4}", self.ndcg.result());
-        let hitrate_score = format!("{:.4}", self.hitrate.result());
-        let popularity_score = format!("{:.4}", self.popularity.result());
-        let precision_score = format!("{:.4}", self.precision.result());
-        let coverage_score = format!("{:.4}", self.coverage.result());
-        let recall_score = format!("{:.4}", self.recall.result());
-        let f1_score = format!("{:.4}", self.f1.result());
-        format!(
-            "{},{},{},{},{},{},{},{},{}",
-            n,
-            mrr_score,
-            ndcg_score,
-            hitrate_score,
-            popularity_score,
-            precision_score,
-            coverage_score,
-            recall_score,
-            f1_score
-        )
+        let scores = self
+            .metric_values()
+            .iter()
+            .map(|metric_value| format!("{:.4}", metric_value.value))
+            .join(",");
+        format!("{},{}", self.n, scores)
     }
 
     pub fn get_name(&self) -> String {
-        let n_name = "qty_evaluations".to_string();
-        let mrr_name = self.mrr.get_name();
-        let ndcg_name = self.ndcg.get_name();
-        let hitrate_name = self.hitrate.get_name();
-        let popularity_name = self.popularity.get_name();
-        let precision_name = self.precision.get_name();
-        let coverage_name = self.coverage.get_name();
-        let recall_name = self.recall.get_name();
-        let f1_name = self.f1.get_name();
-        format!(
-            "{},{},{},{},{},{},{},{},{}",
-            n_name,
-            mrr_name,
-            ndcg_name,
-            hitrate_name,
-            popularity_name,
-            precision_name,
-            coverage_name,
-            recall_name,
-            f1_name
-        )
+        let names = self
+            .metric_values()
+            .into_iter()
+            .map(|metric_value| metric_value.name)
+            .join(",");
+        format!("qty_evaluations,{}", names)
+    }
+
+    /// The metrics as a csv matrix with one row per cutoff and one column per metric.
+    pub fn result_table(&self) -> String {
+        let mut rows = Vec::with_capacity(self.cutoffs.len() + 1);
+        if let Some(cutoff_metrics) = self.cutoffs.first() {
+            let names = cutoff_metrics
+                .metric_values()
+                .into_iter()
+                .map(|metric_value| metric_value.metric)
+                .join(",");
+            rows.push(format!("cutoff,{}", names));
+        }
+        for cutoff_metrics in self.cutoffs.iter() {
+            let scores = cutoff_metrics
+                .metric_values()
+                .iter()
+                .map(|metric_value| format!("{:.4}", metric_value.value))
+                .join(",");
+            rows.push(format!("{},{}", cutoff_metrics.cutoff, scores));
+        }
+        rows.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod evaluation_reporter_test {
+    use super::*;
+
+    #[test]
+    fn should_merge_to_the_sequential_result() {
+        let training_df = vec![(1, 1, 0), (1, 2, 0), (2, 2, 0), (2, 3, 0), (3, 4, 0)];
+        let evaluations: Vec<(Vec<u64>, Vec<u64>)> = vec![
+            (vec![1, 2, 3], vec![2, 4]),
+            (vec![4, 3], vec![1]),
+            (vec![2, 1], vec![2]),
+            (vec![], vec![3]),
+        ];
+        let empty_reporter = EvaluationReporter::new(&training_df, 2);
+
+        let mut sequential = empty_reporter.clone();
+        for (recommendations, next_items) in evaluations.iter() {
+            sequential.add(recommendations, next_items);
+        }
+        let mut merged = empty_reporter.clone();
+        for chunk in evaluations.chunks(3) {
+            let mut partial = empty_reporter.clone();
+            for (recommendations, next_items) in chunk.iter() {
+                partial.add(recommendations, next_items);
+            }
+            merged.merge(partial);
+        }
+
+        assert_eq!(4, merged.qty_evaluations());
+        assert_eq!(sequential.result(), merged.result());
+    }
+
+    #[test]
+    fn should_evaluate_every_cutoff_in_one_pass() {
+        let training_df = vec![(1, 1, 0), (1, 2, 0), (2, 2, 0), (2, 3, 0), (3, 4, 0)];
+        let mut reporter = EvaluationReporter::with_cutoffs(&training_df, &[1, 2]);
+        reporter.add(&[1, 2, 3], &[2]);
+
+        let mrr = |name: &str| {
+            let metric_values = reporter.metric_values();
+            metric_values.iter().find(|m| m.name == name).map(|m| m.value)
+        };
+        assert_eq!(Some(0.0), mrr("Mrr@1"));
+        assert_eq!(Some(0.5), mrr("Mrr@2"));
+        assert_eq!(
+            "cutoff,Mrr,Ndcg,HitRate,Popularity,Precision,Coverage,Recall,CappedRecall,Map,F1score,ILD,Novelty,Serendipity,Gini",
+            reporter.result_table().lines().next().unwrap()
+        );
+        assert_eq!(3, reporter.result_table().lines().count());
+    }
+
+    #[test]
+    fn should_pick_the_targets_by_protocol() {
+        let remaining_items = [4, 5, 6];
+        for (name, expected) in [("next_item", &[4][..]), ("remaining_items", &[4, 5, 6]), ("next_items:2", &[4, 5])] {
+            let protocol: EvaluationProtocol = name.parse().unwrap();
+            assert_eq!(name, protocol.to_string());
+            assert_eq!(expected, protocol.target_items(&remaining_items));
+        }
+        assert_eq!(&[4, 5, 6], EvaluationProtocol::NextItems(5).target_items(&remaining_items));
+        assert!("next_items:0".parse::<EvaluationProtocol>().is_err());
+        assert!("last_item".parse::<EvaluationProtocol>().is_err());
+
+        let training_df = vec![(1, 1, 0), (1, 2, 0), (2, 2, 0), (2, 3, 0), (3, 4, 0)];
+        let mut next_item = EvaluationReporter::new(&training_df, 2).with_protocol(EvaluationProtocol::NextItem);
+        let mut remaining = EvaluationReporter::new(&training_df, 2);
+        next_item.add(&[1, 2], &[1, 2]);
+        remaining.add(&[1, 2], &[1, 2]);
+        let recall = |reporter: &EvaluationReporter| {
+            reporter.metric_values().into_iter().find(|m| m.metric == "Recall").unwrap().value
+        };
+        assert!((recall(&next_item) - 1.0).abs() < f64::EPSILON);
+        assert!((recall(&remaining) - 1.0).abs() < f64::EPSILON);
+        next_item.add(&[3], &[1, 3]);
+        remaining.add(&[3], &[1, 3]);
+        assert!((recall(&next_item) - 0.5).abs() < f64::EPSILON);
+        assert!((recall(&remaining) - 0.75).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn should_parse_cutoffs() {
+        assert_eq!(Ok(vec![1, 5, 20]), parse_cutoffs("20, 5,1,5"));
+        assert!(parse_cutoffs("0").is_err());
+        assert!(parse_cutoffs("5,top").is_err());
     }
 }
@@ -2,6 +2,7 @@ use crate::metrics::precision::Precision;
 use crate::metrics::recall::Recall;
 use crate::metrics::SessionMetric;
 
+#[derive(Clone)]
 pub struct F1score {
     precision: Precision,
     recall: Recall,
@@ -38,6 +39,11 @@ impl SessionMetric for F1score {
         }
     }
 
+    fn merge(&mut self, other: Self) {
+        self.precision.merge(other.precision);
+        self.recall.merge(other.recall);
+    }
+
     fn get_name(&self) -> String {
         format!("F1score@{}", self.length)
     }
@@ -0,0 +1,94 @@
+use crate::io::{ItemId, Time, TrainingSessionId};
+use crate::metrics::{SessionMetric, TrainingItems};
+
+use hashbrown::HashMap;
+use std::sync::Arc;
+
+/// The Gini coefficient of how often each catalog item is recommended, `0` when all items are
+/// recommended equally often and close to `1` when a few items get all recommendations. The catalog
+/// are the training items, items that are never recommended count with a frequency of zero.
+#[derive(Clone)]
+pub struct Gini {
+    recommendation_frequencies: HashMap<ItemId, usize>,
+    training_items: Arc<TrainingItems>,
+    length: usize,
+}
+
+impl Gini {
+    pub fn new(training_df: &[(TrainingSessionId, ItemId, Time)], length: usize) -> Gini {
+        Gini::with_training_items(Arc::new(TrainingItems::new(training_df)), length)
+    }
+
+    pub fn with_training_items(training_items: Arc<TrainingItems>, length: usize) -> Gini {
+        Gini {
+            recommendation_frequencies: HashMap::new(),
+            training_items,
+            length,
+        }
+    }
+}
+
+impl SessionMetric for Gini {
+    fn add(&mut self, recommendations: &[u64], _next_items: &[u64]) {
+        for item_id in recommendations.iter().take(self.length) {
+            *self.recommendation_frequencies.entry(*item_id).or_insert(0) += 1;
+        }
+    }
+
+    fn result(&self) -> f64 {
+        let catalog = &self.training_items.catalog;
+        let qty_recommended_outside_catalog = self
+            .recommendation_frequencies
+            .keys()
+            .filter(|item_id| catalog.binary_search(item_id).is_err())
+            .count();
+        let qty_items = catalog.len() + qty_recommended_outside_catalog;
+        let mut frequencies = self.recommendation_frequencies.values().copied().collect::<Vec<_>>();
+        let total: usize = frequencies.iter().sum();
+        if qty_items == 0 || total == 0 {
+            return 0.0;
+        }
+        frequencies.sort_unstable();
+        // The items that are never recommended come first in the ascending order.
+        let offset = qty_items - frequencies.len();
+        let weighted_sum: f64 = frequencies
+            .iter()
+            .enumerate()
+            .map(|(index, frequency)| (2 * (offset + index + 1)) as f64 * *frequency as f64)
+            .sum();
+        let n = qty_items as f64;
+        weighted_sum / (n * total as f64) - (n + 1.0) / n
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (item_id, frequency) in other.recommendation_frequencies {
+            *self.recommendation_frequencies.entry(item_id).or_insert(0) += frequency;
+        }
+    }
+
+    fn get_name(&self) -> String {
+        format!("Gini@{}", self.length)
+    }
+}
+
+#[cfg(test)]
+mod gini_test {
+    use super::*;
+
+    #[test]
+    fn should_measure_the_concentration_of_recommendations() {
+        let training_df = vec![(1, 1, 0), (1, 2, 0), (2, 3, 0), (2, 4, 0)];
+        let mut equal = Gini::new(&training_df, 20);
+        equal.add(&[1, 2], &[]);
+        let mut other = Gini::new(&training_df, 20);
+        other.add(&[3, 4], &[]);
+        equal.merge(other);
+        assert!(equal.result().abs() < 1e-9);
+
+        let mut concentrated = Gini::new(&training_df, 20);
+        concentrated.add(&[1], &[]);
+        concentrated.add(&[1], &[]);
+        assert!((0.75 - concentrated.result()).abs() < 1e-9);
+        assert_eq!(0.0, Gini::new(&training_df, 20).result());
+    }
+}
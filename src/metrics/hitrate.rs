@@ -3,6 +3,7 @@ use crate::metrics::SessionMetric;
 use itertools::Itertools;
 use std::cmp;
 
+#[derive(Clone)]
 pub struct HitRate {
     sum_of_scores: f64,
     qty: usize,
@@ -41,6 +42,11 @@ impl SessionMetric for HitRate {
         }
     }
 
+    fn merge(&mut self, other: Self) {
+        self.sum_of_scores += other.sum_of_scores;
+        self.qty += other.qty;
+    }
+
     fn get_name(&self) -> String {
         format!("HitRate@{}", self.length)
     }
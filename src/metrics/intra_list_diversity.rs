@@ -0,0 +1,129 @@
+use crate::io::{ItemId, Time, TrainingSessionId};
+use crate::metrics::{SessionMetric, TrainingItems};
+
+use itertools::Itertools;
+use std::cmp;
+use std::sync::Arc;
+
+/// The average dissimilarity between all pairs of recommended items, where the similarity of two
+/// items is the cosine similarity of the most recent training sessions they occur in.
+/// Recommendation lists with fewer than two items are not counted.
+#[derive(Clone)]
+pub struct IntraListDiversity {
+    sum_of_scores: f64,
+    qty: usize,
+    training_items: Arc<TrainingItems>,
+    length: usize,
+}
+
+impl IntraListDiversity {
+    pub fn new(training_df: &[(TrainingSessionId, ItemId, Time)], length: usize) -> IntraListDiversity {
+        IntraListDiversity::with_training_items(Arc::new(TrainingItems::new(training_df)), length)
+    }
+
+    pub fn with_training_items(training_items: Arc<TrainingItems>, length: usize) -> IntraListDiversity {
+        IntraListDiversity {
+            sum_of_scores: 0.0,
+            qty: 0,
+            training_items,
+            length,
+        }
+    }
+
+    fn similarity(&self, item_a: &ItemId, item_b: &ItemId) -> f64 {
+        let item_to_sessions = &self.training_items.sessions;
+        let (sessions_a, sessions_b) = match (item_to_sessions.get(item_a), item_to_sessions.get(item_b)) {
+            (Some(sessions_a), Some(sessions_b)) => (sessions_a, sessions_b),
+            _ => return 0.0,
+        };
+        // Both session lists are sorted, count the common sessions in a single pass.
+        let mut qty_common = 0;
+        let (mut index_a, mut index_b) = (0, 0);
+        while index_a < sessions_a.len() && index_b < sessions_b.len() {
+            match sessions_a[index_a].cmp(&sessions_b[index_b]) {
+                cmp::Ordering::Less => index_a += 1,
+                cmp::Ordering::Greater => index_b += 1,
+                cmp::Ordering::Equal => {
+                    qty_common += 1;
+                    index_a += 1;
+                    index_b += 1;
+                }
+            }
+        }
+        qty_common as f64 / ((sessions_a.len() * sessions_b.len()) as f64).sqrt()
+    }
+}
+
+impl SessionMetric for IntraListDiversity {
+    fn add(&mut self, recommendations: &[u64], _next_items: &[u64]) {
+        let items = recommendations
+            .iter()
+            .take(cmp::min(recommendations.len(), self.length))
+            .unique()
+            .collect_vec();
+        if items.len() < 2 {
+            return;
+        }
+        let mut sum_of_dissimilarities = 0_f64;
+        let mut qty_pairs = 0;
+        for (item_a, item_b) in items.iter().tuple_combinations() {
+            sum_of_dissimilarities += 1.0 - self.similarity(item_a, item_b);
+            qty_pairs += 1;
+        }
+        self.sum_of_scores += sum_of_dissimilarities / qty_pairs as f64;
+        self.qty += 1;
+    }
+
+    fn result(&self) -> f64 {
+        if self.qty > 0 {
+            self.sum_of_scores / self.qty as f64
+        } else {
+            0.0
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.sum_of_scores += other.sum_of_scores;
+        self.qty += other.qty;
+    }
+
+    fn get_name(&self) -> String {
+        format!("ILD@{}", self.length)
+    }
+}
+
+#[cfg(test)]
+mod intra_list_diversity_test {
+    use super::*;
+
+    #[test]
+    fn should_only_compare_the_most_recent_sessions_of_an_item() {
+        // 1 and 2 co-occur in many old sessions, 1 and 3 in all recent sessions of 1.
+        let mut training_df = Vec::new();
+        for session_id in 0..1000 {
+            training_df.push((session_id, 1, session_id as Time));
+            let other_item = if session_id < 500 { 2 } else { 3 };
+            training_df.push((session_id, other_item, session_id as Time));
+        }
+        let mut mymetric = IntraListDiversity::new(&training_df, 20);
+        mymetric.add(&[1, 2], &[]);
+        assert_eq!(1.0, mymetric.result());
+        mymetric.add(&[1, 3], &[]);
+        assert_eq!(0.5, mymetric.result());
+    }
+
+    #[test]
+    fn should_calculate_intra_list_diversity() {
+        // 1 and 2 always co-occur, 3 never co-occurs with them.
+        let training_df = vec![(1, 1, 0), (1, 2, 0), (2, 1, 0), (2, 2, 0), (3, 3, 0)];
+        let mut mymetric = IntraListDiversity::new(&training_df, 20);
+        mymetric.add(&[1, 2], &[]);
+        assert_eq!(0.0, mymetric.result());
+        mymetric.add(&[1, 3], &[]);
+        assert_eq!(0.5, mymetric.result());
+        // single items have no pairs and are not counted
+        mymetric.add(&[1], &[]);
+        assert_eq!(0.5, mymetric.result());
+        assert_eq!("ILD@20", mymetric.get_name());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::metrics::SessionMetric;
+use std::collections::HashSet;
+
+/// Mean average precision, the average of the precision at the rank of every relevant recommendation
+/// divided by `min(k, |next_items|)`, so a perfect top `k` scores `1` even with more next items.
+#[derive(Clone)]
+pub struct Map {
+    sum_of_scores: f64,
+    qty: usize,
+    length: usize,
+}
+
+impl Map {
+    pub fn new(length: usize) -> Map {
+        Map {
+            sum_of_scores: 0_f64,
+            qty: 0,
+            length,
+        }
+    }
+}
+
+impl SessionMetric for Map {
+    fn add(&mut self, recommendations: &[u64], next_items: &[u64]) {
+        self.qty += 1;
+        let unique_next_items: HashSet<&u64> = next_items.iter().collect();
+        let max_hits = unique_next_items.len().min(self.length);
+        if max_hits == 0 {
+            return;
+        }
+        let mut seen = HashSet::new();
+        let mut qty_hits = 0;
+        let mut sum_of_precisions = 0_f64;
+        for (index, item_id) in recommendations.iter().take(self.length).enumerate() {
+            if unique_next_items.contains(item_id) && seen.insert(item_id) {
+                qty_hits += 1;
+                sum_of_precisions += qty_hits as f64 / (index as f64 + 1_f64);
+            }
+        }
+        self.sum_of_scores += sum_of_precisions / max_hits as f64
+    }
+
+    fn result(&self) -> f64 {
+        if self.qty > 0 {
+            self.sum_of_scores / self.qty as f64
+        } else {
+            0.0
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.sum_of_scores += other.sum_of_scores;
+        self.qty += other.qty;
+    }
+
+    fn get_name(&self) -> String {
+        format!("Map@{}", self.length)
+    }
+}
+
+#[cfg(test)]
+mod map_test {
+    use super::*;
+
+    #[test]
+    fn should_calculate_map() {
+        let mut mymetric = Map::new(20);
+        let recommendations: Vec<u64> = vec![
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        ];
+        let actual_next_items: Vec<u64> = vec![3, 55, 3, 4];
+        mymetric.add(&recommendations, &actual_next_items);
+        // (1/3 + 2/4) / 3
+        assert!((0.2777777777777778 - mymetric.result()).abs() < f64::EPSILON);
+        assert_eq!("Map@20", mymetric.get_name());
+    }
+
+    #[test]
+    fn should_score_a_perfect_top_k_with_one() {
+        let mut mymetric = Map::new(2);
+        mymetric.add(&[1, 2, 3], &[2, 1, 3]);
+        assert!((1.0 - mymetric.result()).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn should_merge_map() {
+        let mut mymetric = Map::new(20);
+        mymetric.add(&[1, 2], &[2]);
+        let mut other = Map::new(20);
+        other.add(&[1, 2], &[1]);
+        other.add(&[1, 2], &[3]);
+        mymetric.merge(other);
+        assert_eq!(0.5, mymetric.result());
+    }
+}
@@ -1,15 +1,97 @@
+pub mod breakdown;
+pub mod capped_recall;
 pub mod coverage;
 pub mod evaluation_reporter;
 pub mod f1score;
+pub mod gini;
 pub mod hitrate;
+pub mod intra_list_diversity;
+pub mod map;
 pub mod mrr;
 pub mod ndcg;
+pub mod novelty;
 pub mod popularity;
 pub mod precision;
 pub mod recall;
+pub mod report;
+pub mod serendipity;
+pub mod significance;
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::io::{ItemId, Time, TrainingSessionId};
+
+// Only the most recent training sessions of an item are kept for the item similarities, which
+// bounds the work per recommended item pair like the most recent sessions per item of VMIS-kNN.
+const MAX_SESSIONS_PER_ITEM: usize = 500;
+
+/// What the metrics know about the training items, derived once per evaluation and shared by the
+/// metrics at every cutoff.
+pub struct TrainingItems {
+    /// The amount of training interactions per item.
+    pub(crate) frequencies: HashMap<ItemId, usize>,
+    pub(crate) max_frequency: usize,
+    pub(crate) qty_interactions: usize,
+    /// The items ordered by descending frequency, ties by item id.
+    pub(crate) by_frequency: Vec<ItemId>,
+    /// The item ids in ascending order.
+    pub(crate) catalog: Vec<ItemId>,
+    /// The `MAX_SESSIONS_PER_ITEM` most recent training sessions of every item in ascending order.
+    pub(crate) sessions: HashMap<ItemId, Vec<TrainingSessionId>>,
+}
+
+impl TrainingItems {
+    pub fn new(training_df: &[(TrainingSessionId, ItemId, Time)]) -> TrainingItems {
+        let mut frequencies = HashMap::new();
+        let mut timed_sessions: HashMap<ItemId, Vec<(Time, TrainingSessionId)>> = HashMap::new();
+        for (session_id, item_id, time) in training_df.iter() {
+            *frequencies.entry(*item_id).or_insert(0) += 1;
+            timed_sessions.entry(*item_id).or_default().push((*time, *session_id));
+        }
+        let sessions = timed_sessions
+            .into_iter()
+            .map(|(item_id, mut timed_session_ids)| {
+                // The most recent first, ties by session id.
+                timed_session_ids.sort_unstable_by(|(time_a, session_a), (time_b, session_b)| {
+                    time_b.cmp(time_a).then(session_a.cmp(session_b))
+                });
+                let mut session_ids = timed_session_ids
+                    .into_iter()
+                    .map(|(_time, session_id)| session_id)
+                    .unique()
+                    .take(MAX_SESSIONS_PER_ITEM)
+                    .collect_vec();
+                session_ids.sort_unstable();
+                (item_id, session_ids)
+            })
+            .collect();
+        let by_frequency = frequencies
+            .iter()
+            .sorted_by(|(item_a, frequency_a), (item_b, frequency_b)| {
+                frequency_b.cmp(frequency_a).then(item_a.cmp(item_b))
+            })
+            .map(|(item_id, _frequency)| *item_id)
+            .collect();
+        TrainingItems {
+            max_frequency: frequencies.values().copied().max().unwrap_or(0),
+            qty_interactions: training_df.len(),
+            by_frequency,
+            catalog: frequencies.keys().copied().sorted().collect(),
+            frequencies,
+            sessions,
+        }
+    }
+}
 
 pub trait SessionMetric {
     fn add(&mut self, recommendations: &[u64], next_items: &[u64]);
     fn result(&self) -> f64;
+    /// Adds the evaluations of `other`, an accumulator of the same metric over other sessions,
+    /// so sessions can be evaluated in parallel.
+    fn merge(&mut self, other: Self)
+    where
+        Self: Sized;
     fn get_name(&self) -> String;
 }
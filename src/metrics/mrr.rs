@@ -2,6 +2,7 @@ use crate::metrics::SessionMetric;
 use itertools::Itertools;
 use std::cmp;
 
+#[derive(Clone)]
 pub struct Mrr {
     sum_of_scores: f64,
     qty: usize,
@@ -40,6 +41,11 @@ impl SessionMetric for Mrr {
         }
     }
 
+    fn merge(&mut self, other: Self) {
+        self.sum_of_scores += other.sum_of_scores;
+        self.qty += other.qty;
+    }
+
     fn get_name(&self) -> String {
         format!("Mrr@{}", self.length)
     }
@@ -60,4 +66,15 @@ mod mrr_test {
         assert_eq!(0.3333333333333333, mymetric.result());
         assert_eq!("Mrr@20", mymetric.get_name());
     }
+
+    #[test]
+    fn should_merge_mrr() {
+        let mut mymetric = Mrr::new(20);
+        mymetric.add(&[1, 2], &[2]);
+        let mut other = Mrr::new(20);
+        other.add(&[1, 2], &[1]);
+        other.add(&[1, 2], &[3]);
+        mymetric.merge(other);
+        assert_eq!(0.5, mymetric.result());
+    }
 }
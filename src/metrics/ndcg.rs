@@ -3,6 +3,7 @@ use itertools::Itertools;
 use std::cmp;
 use std::collections::HashSet;
 
+#[derive(Clone)]
 pub struct Ndcg {
     sum_of_scores: f64,
     qty: usize,
@@ -63,6 +64,11 @@ impl SessionMetric for Ndcg {
         }
     }
 
+    fn merge(&mut self, other: Self) {
+        self.sum_of_scores += other.sum_of_scores;
+        self.qty += other.qty;
+    }
+
     fn get_name(&self) -> String {
         format!("Ndcg@{}", self.length)
     }
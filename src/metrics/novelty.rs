@@ -0,0 +1,81 @@
+use crate::io::{ItemId, Time, TrainingSessionId};
+use crate::metrics::{SessionMetric, TrainingItems};
+
+use std::sync::Arc;
+
+/// The mean self-information `-log2(p)` of the recommended items in bits, where `p` is the share of
+/// the training interactions with the item. Items that are not in the training data count as seen once.
+#[derive(Clone)]
+pub struct Novelty {
+    sum_of_scores: f64,
+    qty: usize,
+    training_items: Arc<TrainingItems>,
+    length: usize,
+}
+
+impl Novelty {
+    pub fn new(training_df: &[(TrainingSessionId, ItemId, Time)], length: usize) -> Novelty {
+        Novelty::with_training_items(Arc::new(TrainingItems::new(training_df)), length)
+    }
+
+    pub fn with_training_items(training_items: Arc<TrainingItems>, length: usize) -> Novelty {
+        Novelty {
+            sum_of_scores: 0.0,
+            qty: 0,
+            training_items,
+            length,
+        }
+    }
+
+    fn self_information(&self, item_id: &ItemId) -> f64 {
+        let frequency = self.training_items.frequencies.get(item_id).copied().unwrap_or(1);
+        let qty_interactions = self.training_items.qty_interactions.max(frequency);
+        -(frequency as f64 / qty_interactions as f64).log2()
+    }
+}
+
+impl SessionMetric for Novelty {
+    fn add(&mut self, recommendations: &[u64], _next_items: &[u64]) {
+        let top_recos = &recommendations[..recommendations.len().min(self.length)];
+        self.qty += 1;
+        if !top_recos.is_empty() {
+            let sum: f64 = top_recos.iter().map(|item_id| self.self_information(item_id)).sum();
+            self.sum_of_scores += sum / top_recos.len() as f64;
+        }
+    }
+
+    fn result(&self) -> f64 {
+        if self.qty > 0 {
+            self.sum_of_scores / self.qty as f64
+        } else {
+            0.0
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.sum_of_scores += other.sum_of_scores;
+        self.qty += other.qty;
+    }
+
+    fn get_name(&self) -> String {
+        format!("Novelty@{}", self.length)
+    }
+}
+
+#[cfg(test)]
+mod novelty_test {
+    use super::*;
+
+    #[test]
+    fn should_score_rare_items_higher() {
+        let training_df = vec![(1, 1, 0), (1, 2, 0), (2, 1, 0), (3, 1, 0)];
+        let mut undertest = Novelty::new(&training_df, 20);
+        undertest.add(&[2], &[]);
+        assert!((2.0 - undertest.result()).abs() < 1e-9);
+        let mut other = Novelty::new(&training_df, 20);
+        other.add(&[1, 1], &[]);
+        undertest.merge(other);
+        assert!((undertest.result() - (2.0 - 0.75_f64.log2()) / 2.0).abs() < 1e-9);
+        assert_eq!("Novelty@20", undertest.get_name());
+    }
+}
@@ -1,37 +1,33 @@
 use crate::io::{ItemId, Time, TrainingSessionId};
-use crate::metrics::SessionMetric;
+use crate::metrics::{SessionMetric, TrainingItems};
 
 use itertools::Itertools;
 use itertools::__std_iter::FromIterator;
 use std::cmp;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
+use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct Popularity {
     sum_of_scores: f64,
     qty: usize,
-    popularity_scores: HashMap<u64, i32>,
+    training_items: Arc<TrainingItems>,
     length: usize,
-    max_frequency: i32,
 }
 
 impl Popularity {}
 
 impl Popularity {
     pub fn new(training_df: &[(TrainingSessionId, ItemId, Time)], length: usize) -> Popularity {
-        let mut popularity_scores = HashMap::with_capacity(training_df.len());
-        let mut max_frequency = 0;
-        for (_session_id, item_id, _time) in training_df.iter() {
-            let counter = popularity_scores.entry(*item_id).or_insert(0);
-            *counter += 1;
-            max_frequency = cmp::max(*counter, max_frequency);
-        }
+        Popularity::with_training_items(Arc::new(TrainingItems::new(training_df)), length)
+    }
 
+    pub fn with_training_items(training_items: Arc<TrainingItems>, length: usize) -> Popularity {
         Popularity {
             sum_of_scores: 0.0,
             qty: 0,
-            popularity_scores,
+            training_items,
             length,
-            max_frequency,
         }
     }
 }
@@ -49,7 +45,9 @@ impl SessionMetric for Popularity {
         if !items.is_empty() {
             let mut sum = 0_f64;
             for item in items.iter() {
-                if let Some(item_freq) = self.popularity_scores.get(item) { sum += *item_freq as f64 / self.max_frequency as f64 }
+                if let Some(item_freq) = self.training_items.frequencies.get(item) {
+                    sum += *item_freq as f64 / self.training_items.max_frequency as f64
+                }
             }
             self.sum_of_scores += sum / items.len() as f64;
         }
@@ -63,6 +61,11 @@ impl SessionMetric for Popularity {
         }
     }
 
+    fn merge(&mut self, other: Self) {
+        self.sum_of_scores += other.sum_of_scores;
+        self.qty += other.qty;
+    }
+
     fn get_name(&self) -> String {
         format!("Popularity@{}", self.length)
     }
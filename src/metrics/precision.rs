@@ -2,6 +2,7 @@ use crate::metrics::SessionMetric;
 use std::cmp;
 use std::collections::HashSet;
 
+#[derive(Clone)]
 pub struct Precision {
     sum_of_scores: f64,
     qty: usize,
@@ -51,6 +52,11 @@ impl SessionMetric for Precision {
         }
     }
 
+    fn merge(&mut self, other: Self) {
+        self.sum_of_scores += other.sum_of_scores;
+        self.qty += other.qty;
+    }
+
     fn get_name(&self) -> String {
         format!("Precision@{}", self.length)
     }
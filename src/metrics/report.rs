@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::io::{ItemId, Time, TrainingSessionId};
+use crate::metrics::breakdown::{latency_by_position, BreakdownReporter, BreakdownValue, LatencyBucket};
+use crate::metrics::evaluation_reporter::EvaluationReporter;
+use crate::metrics::significance::{ConfidenceInterval, PairedComparison};
+use crate::stopwatch::Stopwatch;
+
+/// The prediction latency percentiles that are reported.
+pub const LATENCY_PERCENTILES: [f64; 6] = [25.0, 50.0, 75.0, 90.0, 95.0, 99.5];
+
+/// The file format of an evaluation report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    /// One `section,name,value` row per reported value.
+    Csv,
+}
+
+impl ReportFormat {
+    /// The format that matches the extension of `path`, `.json` or `.csv`.
+    pub fn from_path(path: &str) -> Result<Self, String> {
+        Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| format!("Report file must end with .json or .csv: {}", path))
+    }
+
+    /// Checks that a report can be written to `path` before evaluating, returns its format.
+    pub fn validate_path(path: &str) -> Result<Self, String> {
+        let format = ReportFormat::from_path(path)?;
+        match Path::new(path).parent() {
+            Some(directory) if !directory.as_os_str().is_empty() && !directory.is_dir() => {
+                Err(format!("Report directory does not exist: {}", directory.display()))
+            }
+            _ => Ok(format),
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(format!("Unknown report format: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ReportFormat::Json => "json",
+            ReportFormat::Csv => "csv",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The value of one metric at one cutoff, e.g. `Mrr` at `20`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricValue {
+    /// The metric with its cutoff as printed by the evaluator, e.g. `Mrr@20`.
+    pub name: String,
+    pub metric: String,
+    pub cutoff: usize,
+    pub value: f64,
+}
+
+impl MetricValue {
+    pub fn new(name: String, value: f64) -> Self {
+        let (metric, cutoff) = match name.split_once('@') {
+            Some((metric, cutoff)) => (metric.to_string(), cutoff.parse().unwrap_or_default()),
+            None => (name.clone(), 0),
+        };
+        MetricValue {
+            name,
+            metric,
+            cutoff,
+            value,
+        }
+    }
+}
+
+/// A machine readable evaluation result, to track accuracy and latency over releases.
+#[derive(Debug, Default, Serialize)]
+pub struct EvaluationReport {
+    pub config: BTreeMap<String, String>,
+    pub dataset: BTreeMap<String, usize>,
+    pub metrics: Vec<MetricValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baseline_metrics: Option<Vec<MetricValue>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_in_micros: Option<BTreeMap<String, f64>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub breakdowns: Vec<BreakdownValue>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub latency_by_position: Vec<LatencyBucket>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub confidence_intervals: Vec<ConfidenceInterval>,
+    /// The differences with the baseline, or with other predictions, on the same test events.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub comparison: Vec<PairedComparison>,
+}
+
+impl EvaluationReport {
+    pub fn new(reporter: &EvaluationReporter) -> Self {
+        let mut dataset = BTreeMap::new();
+        dataset.insert("qty_evaluations".to_string(), reporter.qty_evaluations());
+        EvaluationReport {
+            dataset,
+            metrics: reporter.metric_values(),
+            ..Default::default()
+        }
+    }
+
+    pub fn add_config<T: ToString>(&mut self, name: &str, value: T) {
+        self.config.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn add_training_data_stats(&mut self, training_df: &[(TrainingSessionId, ItemId, Time)]) {
+        let qty_sessions = training_df.iter().map(|(session_id, _, _)| session_id).unique().count();
+        let qty_items = training_df.iter().map(|(_, item_id, _)| item_id).unique().count();
+        self.dataset.insert("qty_training_interactions".to_string(), training_df.len());
+        self.dataset.insert("qty_training_sessions".to_string(), qty_sessions);
+        self.dataset.insert("qty_training_items".to_string(), qty_items);
+    }
+
+    pub fn set_baseline(&mut self, baseline_reporter: &EvaluationReporter) {
+        self.baseline_metrics = Some(baseline_reporter.metric_values());
+    }
+
+    pub fn set_latency(&mut self, stopwatch: &Stopwatch) {
+        let latency_in_micros = LATENCY_PERCENTILES
+            .iter()
+            .map(|percentile| (format!("p{}", percentile), stopwatch.get_percentile_in_micros(*percentile)))
+            .collect();
+        self.latency_in_micros = Some(latency_in_micros);
+        self.latency_by_position = latency_by_position(stopwatch);
+    }
+
+    pub fn set_breakdowns(&mut self, breakdown_reporter: &BreakdownReporter) {
+        self.breakdowns = breakdown_reporter.values();
+    }
+
+    /// Writes the report in the format that matches the extension of `path`.
+    pub fn write(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let format = ReportFormat::from_path(path)?;
+        let file = File::create(path)?;
+        match format {
+            ReportFormat::Json => serde_json::to_writer_pretty(file, self)?,
+            ReportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(file);
+                writer.write_record(["section", "name", "value"])?;
+                for record in self.csv_records() {
+                    writer.write_record(&record)?;
+                }
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn csv_records(&self) -> Vec<[String; 3]> {
+        let mut records = Vec::new();
+        for (name, value) in self.config.iter() {
+            records.push(["config".to_string(), name.clone(), value.clone()]);
+        }
+        for (name, value) in self.dataset.iter() {
+            records.push(["dataset".to_string(), name.clone(), value.to_string()]);
+        }
+        for metric_value in self.metrics.iter() {
+            records.push(["metrics".to_string(), metric_value.name.clone(), metric_value.value.to_string()]);
+        }
+        if let Some(baseline_metrics) = &self.baseline_metrics {
+            for metric_value in baseline_metrics.iter() {
+                records.push([
+                    "baseline_metrics".to_string(),
+                    metric_value.name.clone(),
+                    metric_value.value.to_string(),
+                ]);
+            }
+        }
+        if let Some(latency_in_micros) = &self.latency_in_micros {
+            for (name, value) in latency_in_micros.iter() {
+                records.push(["latency_in_micros".to_string(), name.clone(), value.to_string()]);
+            }
+        }
+        for breakdown_value in self.breakdowns.iter() {
+            let section = format!("by_{}", breakdown_value.breakdown);
+            let bucket = &breakdown_value.bucket;
+            records.push([section.clone(), format!("{}:qty", bucket), breakdown_value.qty.to_string()]);
+            for metric_value in breakdown_value.metrics.iter() {
+                records.push([
+                    section.clone(),
+                    format!("{}:{}", bucket, metric_value.name),
+                    metric_value.value.to_string(),
+                ]);
+            }
+        }
+        for interval in self.confidence_intervals.iter() {
+            for (bound, value) in [("lower", interval.lower), ("upper", interval.upper)] {
+                records.push([
+                    "confidence_intervals".to_string(),
+                    format!("{}:{}", interval.name, bound),
+                    value.to_string(),
+                ]);
+            }
+        }
+        for comparison in self.comparison.iter() {
+            let values = [
+                ("difference", comparison.difference),
+                ("lower", comparison.lower),
+                ("upper", comparison.upper),
+                ("p_value", comparison.p_value),
+            ];
+            for (name, value) in values {
+                records.push([
+                    "comparison".to_string(),
+                    format!("{}:{}", comparison.name, name),
+                    value.to_string(),
+                ]);
+            }
+        }
+        for latency_bucket in self.latency_by_position.iter() {
+            for (name, value) in latency_bucket.latency_in_micros.iter() {
+                records.push([
+                    "latency_by_position_in_micros".to_string(),
+                    format!("{}:{}", latency_bucket.bucket, name),
+                    value.to_string(),
+                ]);
+            }
+        }
+        records
+    }
+}
+
+#[cfg(test)]
+mod report_test {
+    use super::*;
+
+    fn report() -> EvaluationReport {
+        let training_df = vec![(1, 1, 0), (1, 2, 0), (2, 2, 0)];
+        let mut reporter = EvaluationReporter::new(&training_df, 20);
+        reporter.add(&[1, 2], &[2]);
+        let mut report = EvaluationReport::new(&reporter);
+        report.add_config("algorithm", "vmis_knn");
+        report.add_training_data_stats(&training_df);
+        report
+    }
+
+    #[test]
+    fn should_split_metric_names() {
+        let metric_value = MetricValue::new("Mrr@20".to_string(), 0.5);
+        assert_eq!(("Mrr", 20), (metric_value.metric.as_str(), metric_value.cutoff));
+    }
+
+    #[test]
+    fn should_report_every_metric_and_the_dataset() {
+        let report = report();
+        assert_eq!(Some(&0.5), report.metrics.iter().find(|m| m.name == "Mrr@20").map(|m| &m.value));
+        assert_eq!(Some(&2), report.dataset.get("qty_training_sessions"));
+        assert_eq!(Some(&1), report.dataset.get("qty_evaluations"));
+
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!("vmis_knn", json["config"]["algorithm"]);
+        assert!(json.get("latency_in_micros").is_none());
+
+        let records = report.csv_records();
+        assert!(records.contains(&["metrics".to_string(), "Mrr@20".to_string(), "0.5".to_string()]));
+    }
+
+    #[test]
+    fn should_pick_the_format_by_extension() {
+        assert_eq!(Ok(ReportFormat::Json), ReportFormat::from_path("out/report.json"));
+        assert_eq!(Ok(ReportFormat::Csv), ReportFormat::from_path("report.csv"));
+        assert!(ReportFormat::from_path("report.txt").is_err());
+    }
+
+    #[test]
+    fn should_validate_the_report_path_before_writing() {
+        assert_eq!(Ok(ReportFormat::Csv), ReportFormat::validate_path("report.csv"));
+        assert!(ReportFormat::validate_path("no_such_directory/report.json").is_err());
+
+        let path = std::env::temp_dir().join(format!("serenade_report_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert!(report().write(path).is_err());
+        assert!(!Path::new(path).exists());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::io::{ItemId, Time, TrainingSessionId};
+use crate::metrics::{SessionMetric, TrainingItems};
+
+use itertools::Itertools;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// The share of the `length` recommendations that are both relevant, one of the next items, and
+/// unexpected, not among the `length` most popular training items that a trivial recommender returns.
+#[derive(Clone)]
+pub struct Serendipity {
+    sum_of_scores: f64,
+    qty: usize,
+    expected_items: Arc<HashSet<ItemId>>,
+    length: usize,
+}
+
+impl Serendipity {
+    pub fn new(training_df: &[(TrainingSessionId, ItemId, Time)], length: usize) -> Serendipity {
+        Serendipity::with_training_items(&TrainingItems::new(training_df), length)
+    }
+
+    pub fn with_training_items(training_items: &TrainingItems, length: usize) -> Serendipity {
+        let expected_items = training_items.by_frequency.iter().take(length).copied().collect();
+        Serendipity {
+            sum_of_scores: 0.0,
+            qty: 0,
+            expected_items: Arc::new(expected_items),
+            length,
+        }
+    }
+}
+
+impl SessionMetric for Serendipity {
+    fn add(&mut self, recommendations: &[u64], next_items: &[u64]) {
+        let top_recos = &recommendations[..recommendations.len().min(self.length)];
+        self.qty += 1;
+        let qty_serendipitous = top_recos
+            .iter()
+            .unique()
+            .filter(|item_id| next_items.contains(item_id) && !self.expected_items.contains(item_id))
+            .count();
+        // Relative to the cutoff like `Precision`, so serendipity never exceeds the precision.
+        self.sum_of_scores += qty_serendipitous as f64 / self.length as f64;
+    }
+
+    fn result(&self) -> f64 {
+        if self.qty > 0 {
+            self.sum_of_scores / self.qty as f64
+        } else {
+            0.0
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.sum_of_scores += other.sum_of_scores;
+        self.qty += other.qty;
+    }
+
+    fn get_name(&self) -> String {
+        format!("Serendipity@{}", self.length)
+    }
+}
+
+#[cfg(test)]
+mod serendipity_test {
+    use super::*;
+
+    #[test]
+    fn should_only_count_relevant_unpopular_items() {
+        let training_df = vec![(1, 1, 0), (1, 2, 0), (2, 1, 0), (3, 3, 0)];
+        // with a length of 2, items 1 and 2 are expected
+        let mut undertest = Serendipity::new(&training_df, 2);
+        undertest.add(&[1, 3], &[1, 3]);
+        assert!((0.5 - undertest.result()).abs() < f64::EPSILON);
+        let mut other = Serendipity::new(&training_df, 2);
+        other.add(&[2, 4], &[2]);
+        undertest.merge(other);
+        assert!((0.25 - undertest.result()).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,285 @@
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::io::EventKey;
+use crate::metrics::hitrate::HitRate;
+use crate::metrics::mrr::Mrr;
+use crate::metrics::ndcg::Ndcg;
+use crate::metrics::precision::Precision;
+use crate::metrics::recall::Recall;
+use crate::metrics::SessionMetric;
+
+/// The seed of the resampling, so repeated evaluations report the same intervals.
+const BOOTSTRAP_SEED: u64 = 42;
+
+/// The scores of every single test event for the metrics that are an average over the test events,
+/// in the order of the events. Merge the scores of consecutive parts of the test set in order, so
+/// the scores of two evaluations on the same test events can be paired by their event keys.
+#[derive(Clone)]
+pub struct EventScores {
+    length: usize,
+    names: Vec<String>,
+    keys: Vec<EventKey>,
+    scores: Vec<Vec<f64>>,
+}
+
+impl EventScores {
+    pub fn new(length: usize) -> EventScores {
+        let names = EventScores::metrics(length)
+            .iter()
+            .map(|metric| metric.get_name())
+            .collect_vec();
+        EventScores {
+            length,
+            keys: Vec::new(),
+            scores: vec![Vec::new(); names.len()],
+            names,
+        }
+    }
+
+    fn metrics(length: usize) -> Vec<Box<dyn SessionMetric>> {
+        vec![
+            Box::new(Mrr::new(length)),
+            Box::new(Ndcg::new(length)),
+            Box::new(HitRate::new(length)),
+            Box::new(Precision::new(length)),
+            Box::new(Recall::new(length)),
+        ]
+    }
+
+    pub fn add(&mut self, key: EventKey, recommendations: &[u64], next_items: &[u64]) {
+        self.keys.push(key);
+        for (index, mut metric) in EventScores::metrics(self.length).into_iter().enumerate() {
+            metric.add(recommendations, next_items);
+            self.scores[index].push(metric.result());
+        }
+    }
+
+    pub fn merge(&mut self, other: EventScores) {
+        self.keys.extend(other.keys);
+        for (scores, other_scores) in self.scores.iter_mut().zip(other.scores) {
+            scores.extend(other_scores);
+        }
+    }
+
+    pub fn qty_events(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Percentile bootstrap confidence intervals of the mean of every metric.
+    pub fn confidence_intervals(&self, qty_resamples: usize, confidence_level: f64) -> Vec<ConfidenceInterval> {
+        self.names
+            .iter()
+            .zip(self.scores.iter())
+            .map(|(name, scores)| {
+                let (lower, upper) = bootstrap_interval(scores, qty_resamples, confidence_level);
+                ConfidenceInterval {
+                    name: name.clone(),
+                    value: mean(scores),
+                    lower,
+                    upper,
+                }
+            })
+            .collect()
+    }
+
+    /// Compares every metric with the `other` scores of the same test events by a paired bootstrap,
+    /// fails when the test events differ.
+    pub fn compare(
+        &self,
+        other: &EventScores,
+        qty_resamples: usize,
+        confidence_level: f64,
+    ) -> Result<Vec<PairedComparison>, String> {
+        if self.qty_events() != other.qty_events() {
+            return Err(format!(
+                "Paired comparison needs the same test events, got {} and {} events",
+                self.qty_events(),
+                other.qty_events()
+            ));
+        }
+        if let Some(((session_id, position), (other_session_id, other_position))) =
+            self.keys.iter().zip(other.keys.iter()).find(|(key, other_key)| key != other_key)
+        {
+            return Err(format!(
+                "Paired comparison needs the same test events, got session {} at position {} and session {} at position {}",
+                session_id, position, other_session_id, other_position
+            ));
+        }
+        let comparisons = self
+            .names
+            .iter()
+            .zip(self.scores.iter().zip(other.scores.iter()))
+            .map(|(name, (scores, other_scores))| {
+                let differences = scores
+                    .iter()
+                    .zip(other_scores.iter())
+                    .map(|(score, other_score)| score - other_score)
+                    .collect_vec();
+                PairedComparison::new(name.clone(), &differences, qty_resamples, confidence_level)
+            })
+            .collect();
+        Ok(comparisons)
+    }
+}
+
+/// A metric with the bounds of its confidence interval.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfidenceInterval {
+    pub name: String,
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// The difference of a metric between two evaluations on the same test events.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PairedComparison {
+    pub name: String,
+    pub difference: f64,
+    pub lower: f64,
+    pub upper: f64,
+    /// The two-sided bootstrap p-value of a difference of zero.
+    pub p_value: f64,
+}
+
+impl PairedComparison {
+    fn new(name: String, differences: &[f64], qty_resamples: usize, confidence_level: f64) -> PairedComparison {
+        let means = bootstrap_means(differences, qty_resamples);
+        let (lower, upper) = percentile_interval(&means, confidence_level);
+        let p_value = if means.is_empty() {
+            1.0
+        } else {
+            // The resampled means on either side of zero, ties count for both sides.
+            let qty_at_most_zero = means.iter().filter(|mean| **mean <= 0.0).count();
+            let qty_at_least_zero = means.iter().filter(|mean| **mean >= 0.0).count();
+            let qty_beyond_zero = qty_at_most_zero.min(qty_at_least_zero);
+            (2.0 * qty_beyond_zero as f64 / means.len() as f64).min(1.0)
+        };
+        PairedComparison {
+            name,
+            difference: mean(differences),
+            lower,
+            upper,
+            p_value,
+        }
+    }
+}
+
+/// The confidence intervals as a csv table with one row per metric.
+pub fn confidence_interval_table(intervals: &[ConfidenceInterval]) -> String {
+    let mut rows = vec!["metric,value,lower,upper".to_string()];
+    for interval in intervals.iter() {
+        rows.push(format!(
+            "{},{:.4},{:.4},{:.4}",
+            interval.name, interval.value, interval.lower, interval.upper
+        ));
+    }
+    rows.join("\n")
+}
+
+/// The paired comparisons as a csv table with one row per metric.
+pub fn comparison_table(comparisons: &[PairedComparison]) -> String {
+    let mut rows = vec!["metric,difference,lower,upper,p_value".to_string()];
+    for comparison in comparisons.iter() {
+        rows.push(format!(
+            "{},{:.4},{:.4},{:.4},{:.4}",
+            comparison.name, comparison.difference, comparison.lower, comparison.upper, comparison.p_value
+        ));
+    }
+    rows.join("\n")
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        0.0
+    } else {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+}
+
+/// The means of `qty_resamples` samples with replacement of `values`, sorted ascending.
+fn bootstrap_means(values: &[f64], qty_resamples: usize) -> Vec<f64> {
+    if values.is_empty() {
+        return Vec::new();
+    }
+    let mut rng = StdRng::seed_from_u64(BOOTSTRAP_SEED);
+    let mut means = (0..qty_resamples)
+        .map(|_| {
+            let sum: f64 = (0..values.len()).map(|_| values[rng.gen_range(0..values.len())]).sum();
+            sum / values.len() as f64
+        })
+        .collect_vec();
+    means.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    means
+}
+
+/// The central `confidence_level` interval of the sorted `means`, `NaN` without resamples.
+fn percentile_interval(sorted_means: &[f64], confidence_level: f64) -> (f64, f64) {
+    if sorted_means.is_empty() {
+        return (f64::NAN, f64::NAN);
+    }
+    let alpha = (1.0 - confidence_level) / 2.0;
+    let last = sorted_means.len() - 1;
+    let lower = sorted_means[(alpha * last as f64).round() as usize];
+    let upper = sorted_means[((1.0 - alpha) * last as f64).round() as usize];
+    (lower, upper)
+}
+
+fn bootstrap_interval(values: &[f64], qty_resamples: usize, confidence_level: f64) -> (f64, f64) {
+    percentile_interval(&bootstrap_means(values, qty_resamples), confidence_level)
+}
+
+#[cfg(test)]
+mod significance_test {
+    use super::*;
+
+    #[test]
+    fn should_cover_the_mean_with_the_confidence_interval() {
+        let mut undertest = EventScores::new(20);
+        for rank in 1..=50 {
+            let recommendations = (1..=rank).collect_vec();
+            undertest.add((1, rank as usize), &recommendations, &[rank]);
+        }
+        assert_eq!(50, undertest.qty_events());
+        let intervals = undertest.confidence_intervals(1000, 0.95);
+        let mrr = &intervals[0];
+        assert_eq!("Mrr@20", mrr.name);
+        assert!(mrr.lower < mrr.value && mrr.value < mrr.upper);
+        assert_eq!(intervals, undertest.confidence_intervals(1000, 0.95));
+    }
+
+    #[test]
+    fn should_compare_paired_scores() {
+        let mut better = EventScores::new(20);
+        let mut worse = EventScores::new(20);
+        for item in 1..=100 {
+            better.add((item as u32, 1), &[item], &[item]);
+            worse.add((item as u32, 1), &[item + 1, item], &[item]);
+        }
+        let comparisons = better.compare(&worse, 1000, 0.95).unwrap();
+        let mrr = &comparisons[0];
+        assert!((mrr.difference - 0.5).abs() < 1e-9);
+        assert!(mrr.p_value < 0.01);
+
+        let same = better.compare(&better, 1000, 0.95).unwrap();
+        assert!((same[0].p_value - 1.0).abs() < f64::EPSILON);
+
+        assert!(better.compare(&EventScores::new(20), 1000, 0.95).is_err());
+    }
+
+    #[test]
+    fn should_not_compare_different_test_events() {
+        let mut scores = EventScores::new(20);
+        let mut other = EventScores::new(20);
+        for position in 1..=3 {
+            scores.add((1, position), &[1], &[1]);
+            other.add((2, position), &[1], &[1]);
+        }
+        assert_eq!(scores.qty_events(), other.qty_events());
+        let err = scores.compare(&other, 1000, 0.95).unwrap_err();
+        assert!(err.contains("session 1 at position 1 and session 2 at position 1"));
+    }
+}
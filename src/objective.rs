@@ -1,10 +1,19 @@
-use crate::{io, vmisknn};
+use std::sync::Arc;
 
-use crate::vmisknn::vmis_index::VMISIndex;
+use rayon::prelude::*;
+
+use crate::ensemble::{Ensemble, ScoreNormalization};
+use crate::io;
+use crate::popularity::PopularityIndex;
+
+use crate::vmisknn::vmis_index::{NeighborSimilarity, VMISIndex};
 use crate::metrics::mrr::Mrr;
 use crate::metrics::SessionMetric;
+use crate::recommender::{recommend_items, RecommendOptions, Recommender};
 
-// objective function
+// objective function, a `popularity_weight` above zero blends VMIS-kNN with the popularity model.
+// `dedup_consecutive_items` should match the served model, as it changes the evolving sessions.
+#[allow(clippy::too_many_arguments)]
 pub fn objective(
     path_to_training: std::string::String, 
     test_data_file: std::string::String, 
@@ -12,42 +21,64 @@ pub fn objective(
     neighborhood_size_k: i32, 
     last_items_in_session: i32,
     idf_weighting: f64,
-    enable_business_logic: bool) -> f64 {
+    neighbor_similarity: NeighborSimilarity,
+    enable_business_logic: bool,
+    popularity_weight: f64,
+    dedup_consecutive_items: bool) -> f64 {
     
-    let vmis_index = VMISIndex::new_from_csv(&*path_to_training, n_most_recent_sessions as usize, idf_weighting);
+    let vmis_index = Arc::new(
+        VMISIndex::new_from_csv(&path_to_training, n_most_recent_sessions as usize, idf_weighting)
+            .with_neighbor_similarity(neighbor_similarity),
+    );
+
+    let options = RecommendOptions {
+        neighborhood_size_k: neighborhood_size_k as usize,
+        m_most_recent_sessions: n_most_recent_sessions as usize,
+        num_items_to_recommend: 20,
+        enable_business_logic,
+        dedup_consecutive_items,
+    };
+    if popularity_weight > 0.0 {
+        let training_data = io::read_training_data(&path_to_training);
+        let popularity_index = Arc::new(PopularityIndex::new(&training_data, 0));
+        let ensemble = Ensemble::new(
+            vec![(vmis_index, 1.0), (popularity_index, popularity_weight)],
+            ScoreNormalization::MinMax,
+        );
+        evaluate_mrr(&ensemble, &test_data_file, last_items_in_session as usize, &options)
+    } else {
+        evaluate_mrr(vmis_index.as_ref(), &test_data_file, last_items_in_session as usize, &options)
+    }
+}
+
+// The MRR of any recommender on the evolving test sessions, using the last `last_items_in_session` items per state.
+pub fn evaluate_mrr<R: Recommender + ?Sized>(
+    recommender: &R,
+    test_data_file: &str,
+    last_items_in_session: usize,
+    options: &RecommendOptions) -> f64 {
 
-    let ordered_test_sessions = io::read_test_data_evolving(&*test_data_file);
+    let ordered_test_sessions = io::read_test_data_evolving(test_data_file);
 
-    let qty_max_reco_results = 20;
-    let mut mymetric = Mrr::new(qty_max_reco_results);
+    let qty_max_reco_results = options.num_items_to_recommend;
 
+    // The sessions are evaluated in parallel, every rayon job accumulates its own metric.
     ordered_test_sessions
-    .iter()
-    .for_each(|(_session_id, evolving_session_items)| {
+    .par_iter()
+    .fold(|| Mrr::new(qty_max_reco_results), |mut mymetric, (_session_id, evolving_session_items)| {
         for session_state in 1..evolving_session_items.len() {
             // use last x items of evolving session
-            let start_index = if session_state > last_items_in_session as usize {
-                session_state - last_items_in_session as usize
-            } else {
-                0
-            };
+            let start_index = session_state.saturating_sub(last_items_in_session);
             let session: &[u64] = &evolving_session_items[start_index..session_state];
-            let recommendations = vmisknn::predict(
-                &vmis_index,
-                &session,
-                neighborhood_size_k as usize,
-                n_most_recent_sessions as usize,
-                qty_max_reco_results,
-                enable_business_logic
-            );
-            let recommended_items = recommendations
-                .into_sorted_vec()
-                .iter()
-                .map(|scored| scored.id)
-                .collect::<Vec<u64>>();
+            let recommended_items = recommend_items(recommender, session, options);
             let actual_next_items = Vec::from(&evolving_session_items[session_state..]);
             mymetric.add(&recommended_items, &actual_next_items);
-        }            
-    });
-    return mymetric.result();
-}
\ No newline at end of file
+        }
+        mymetric
+    })
+    .reduce(|| Mrr::new(qty_max_reco_results), |mut mymetric, other| {
+        mymetric.merge(other);
+        mymetric
+    })
+    .result()
+}
@@ -0,0 +1,383 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use hashbrown::HashMap;
+
+use crate::io::{ItemId, Time, TrainingSessionId};
+use crate::recommender::{RecommendOptions, Recommender};
+use crate::vmisknn::passes_business_rules;
+use crate::vmisknn::vmis_index::ProductAttributes;
+use crate::vmisknn::ItemScore;
+
+// Amount of ranked items kept, requests skip the items in the evolving session.
+const MAX_RANKED_ITEMS: usize = 1000;
+// Live events are added one at a time, the ranking is refreshed after this many events.
+const RANKING_REFRESH_INTERVAL_EVENTS: usize = 1000;
+// Live events that do not fill a refresh interval are ranked after this much time without events.
+const RANKING_REFRESH_IDLE_TIME: Duration = Duration::from_secs(1);
+
+/// Items with their amount of interactions, the most popular first.
+type Ranking = Arc<Vec<(ItemId, usize)>>;
+
+/// Recommends the items with the most interactions within a time window, regardless of the
+/// evolving session. A strong trivial baseline to measure the lift of VMIS-kNN against.
+pub struct PopularityIndex {
+    window_in_secs: Time,
+    // Events within the window ordered by time, only retained when the window is bounded.
+    events_in_window: VecDeque<(Time, ItemId)>,
+    item_counts: HashMap<ItemId, usize>,
+    ranked_items: Ranking,
+    qty_events_since_ranking: usize,
+    item_to_product_attributes: Arc<HashMap<ItemId, ProductAttributes>>,
+}
+
+impl PopularityIndex {
+    /// Counts the interactions per item in the training data.
+    ///
+    /// # Arguments
+    ///
+    /// * `training_data` - the interactions as read by `io::read_training_data`.
+    /// * `window_in_secs` - only the interactions within this amount of seconds before the most
+    ///   recent interaction are counted. `0` counts all interactions.
+    pub fn new(training_data: &[(TrainingSessionId, ItemId, Time)], window_in_secs: Time) -> Self {
+        // Product attributes are not part of the training data yet, identical to `VMISIndex`.
+        let item_to_product_attributes = training_data
+            .iter()
+            .map(|(_session_id, item_id, _time)| {
+                let attributes = ProductAttributes {
+                    is_adult: false,
+                    is_for_sale: true,
+                };
+                (*item_id, attributes)
+            })
+            .collect();
+        let mut index = PopularityIndex {
+            window_in_secs,
+            events_in_window: VecDeque::new(),
+            item_counts: HashMap::new(),
+            ranked_items: Arc::new(Vec::new()),
+            qty_events_since_ranking: 0,
+            item_to_product_attributes: Arc::new(item_to_product_attributes),
+        };
+        let mut events: Vec<(Time, ItemId)> = training_data
+            .iter()
+            .map(|(_session_id, item_id, time)| (*time, *item_id))
+            .collect();
+        events.sort_unstable();
+        for (time, item_id) in events.into_iter() {
+            index.count_event(item_id, time);
+        }
+        index.refresh_ranking();
+        index
+    }
+
+    /// Adds a live interaction. Interactions that fall out of the window are forgotten.
+    pub fn add_event(&mut self, item_id: ItemId, time: Time) {
+        self.count_event(item_id, time);
+        self.qty_events_since_ranking += 1;
+        if self.qty_events_since_ranking >= RANKING_REFRESH_INTERVAL_EVENTS {
+            self.refresh_ranking();
+        }
+    }
+
+    /// The amount of interactions within the window for the item.
+    pub fn count(&self, item_id: &ItemId) -> usize {
+        self.item_counts.get(item_id).cloned().unwrap_or(0)
+    }
+
+    fn count_event(&mut self, item_id: ItemId, time: Time) {
+        *self.item_counts.entry(item_id).or_insert(0) += 1;
+        if self.window_in_secs == 0 {
+            return;
+        }
+        self.events_in_window.push_back((time, item_id));
+        let min_time = time.saturating_sub(self.window_in_secs);
+        while let Some((oldest_time, oldest_item_id)) = self.events_in_window.front().cloned() {
+            if oldest_time >= min_time {
+                break;
+            }
+            self.events_in_window.pop_front();
+            if let Some(count) = self.item_counts.get_mut(&oldest_item_id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.item_counts.remove(&oldest_item_id);
+                }
+            }
+        }
+    }
+
+    fn refresh_ranking(&mut self) {
+        let mut ranked_items: Vec<(ItemId, usize)> = self
+            .item_counts
+            .iter()
+            .map(|(item_id, count)| (*item_id, *count))
+            .collect();
+        // Ties are broken by item id to keep the ranking reproducible.
+        ranked_items.sort_unstable_by(|(item_a, count_a), (item_b, count_b)| {
+            count_b.cmp(count_a).then(item_a.cmp(item_b))
+        });
+        ranked_items.truncate(MAX_RANKED_ITEMS);
+        self.ranked_items = Arc::new(ranked_items);
+        self.qty_events_since_ranking = 0;
+    }
+}
+
+impl Recommender for PopularityIndex {
+    /// Returns the most popular items excluding the items of the evolving session, scored by their amount of interactions.
+    fn recommend(&self, evolving_session: &[ItemId], options: &RecommendOptions) -> Vec<ItemScore> {
+        recommend_ranked(&self.ranked_items, &self.item_to_product_attributes, evolving_session, options)
+    }
+}
+
+// With business logic, items without product attributes, e.g. only seen in live events, are skipped.
+fn recommend_ranked(
+    ranked_items: &[(ItemId, usize)],
+    item_to_product_attributes: &HashMap<ItemId, ProductAttributes>,
+    evolving_session: &[ItemId],
+    options: &RecommendOptions,
+) -> Vec<ItemScore> {
+    let current_item_attribs = evolving_session
+        .last()
+        .and_then(|item_id| item_to_product_attributes.get(item_id));
+    ranked_items
+        .iter()
+        .filter(|(item_id, _count)| !evolving_session.contains(item_id))
+        .filter(|(item_id, _count)| {
+            !options.enable_business_logic
+                || passes_business_rules(current_item_attribs, item_to_product_attributes.get(item_id))
+        })
+        .take(options.num_items_to_recommend)
+        .map(|(item_id, count)| ItemScore::new(*item_id, *count as f64))
+        .collect()
+}
+
+enum LiveUpdate {
+    Event(ItemId, Time),
+    // Ranks the events received so far and acknowledges when the new ranking is visible.
+    Refresh(Sender<()>),
+}
+
+/// The popularity model while serving, which also counts the live events. Requests only send the
+/// events to a background thread that owns the counts, so they never wait for each other. The
+/// thread ranks the items off-request and swaps the new ranking in.
+pub struct LivePopularityIndex {
+    ranked_items: Arc<RwLock<Ranking>>,
+    item_to_product_attributes: Arc<HashMap<ItemId, ProductAttributes>>,
+    updates: Sender<LiveUpdate>,
+}
+
+impl LivePopularityIndex {
+    pub fn new(mut index: PopularityIndex) -> Self {
+        let ranked_items = Arc::new(RwLock::new(index.ranked_items.clone()));
+        let item_to_product_attributes = index.item_to_product_attributes.clone();
+        let (updates, receiver) = channel();
+        let published_ranking = ranked_items.clone();
+        thread::spawn(move || {
+            LivePopularityIndex::apply_updates(&mut index, receiver, &published_ranking)
+        });
+        LivePopularityIndex {
+            ranked_items,
+            item_to_product_attributes,
+            updates,
+        }
+    }
+
+    /// Queues a live interaction, it is ranked within `RANKING_REFRESH_INTERVAL_EVENTS` events or
+    /// `RANKING_REFRESH_IDLE_TIME`.
+    pub fn add_event(&self, item_id: ItemId, time: Time) {
+        if self.updates.send(LiveUpdate::Event(item_id, time)).is_err() {
+            eprintln!("The popularity updater stopped, live event for item {} ignored", item_id);
+        }
+    }
+
+    /// Ranks the queued events and waits until the new ranking is used.
+    pub fn refresh(&self) {
+        let (done, acknowledgement) = channel();
+        if self.updates.send(LiveUpdate::Refresh(done)).is_ok() {
+            acknowledgement.recv().ok();
+        }
+    }
+
+    fn apply_updates(
+        index: &mut PopularityIndex,
+        receiver: Receiver<LiveUpdate>,
+        published_ranking: &RwLock<Ranking>,
+    ) {
+        let publish = |index: &mut PopularityIndex| {
+            index.refresh_ranking();
+            *published_ranking.write().unwrap() = index.ranked_items.clone();
+        };
+        loop {
+            match receiver.recv_timeout(RANKING_REFRESH_IDLE_TIME) {
+                Ok(LiveUpdate::Event(item_id, time)) => {
+                    index.count_event(item_id, time);
+                    index.qty_events_since_ranking += 1;
+                    if index.qty_events_since_ranking >= RANKING_REFRESH_INTERVAL_EVENTS {
+                        publish(index);
+                    }
+                }
+                Ok(LiveUpdate::Refresh(done)) => {
+                    publish(index);
+                    done.send(()).ok();
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if index.qty_events_since_ranking > 0 {
+                        publish(index);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+}
+
+impl Recommender for LivePopularityIndex {
+    fn recommend(&self, evolving_session: &[ItemId], options: &RecommendOptions) -> Vec<ItemScore> {
+        // Clone the ranking, so a refresh is never blocked for longer than that.
+        let ranked_items = self.ranked_items.read().unwrap().clone();
+        recommend_ranked(&ranked_items, &self.item_to_product_attributes, evolving_session, options)
+    }
+}
+
+/// The live popularity model of serving, only built on first use. Reading and counting the
+/// training data again is skipped when neither the algorithm nor `/v1/popular` needs it.
+pub struct LazyLivePopularityIndex {
+    index: OnceLock<LivePopularityIndex>,
+    build_index: Box<dyn Fn() -> PopularityIndex + Send + Sync>,
+}
+
+impl LazyLivePopularityIndex {
+    pub fn new(build_index: impl Fn() -> PopularityIndex + Send + Sync + 'static) -> Self {
+        LazyLivePopularityIndex {
+            index: OnceLock::new(),
+            build_index: Box::new(build_index),
+        }
+    }
+
+    /// The live popularity model, built from the training data by the first caller.
+    pub fn get(&self) -> &LivePopularityIndex {
+        self.index.get_or_init(|| LivePopularityIndex::new((self.build_index)()))
+    }
+
+    pub fn is_built(&self) -> bool {
+        self.index.get().is_some()
+    }
+}
+
+impl Recommender for LazyLivePopularityIndex {
+    fn recommend(&self, evolving_session: &[ItemId], options: &RecommendOptions) -> Vec<ItemScore> {
+        self.get().recommend(evolving_session, options)
+    }
+}
+
+#[cfg(test)]
+mod popularity_test {
+    use super::*;
+    use crate::recommender::recommend_items;
+
+    fn options(num_items_to_recommend: usize) -> RecommendOptions {
+        RecommendOptions {
+            neighborhood_size_k: 500,
+            m_most_recent_sessions: 500,
+            num_items_to_recommend,
+            enable_business_logic: false,
+            dedup_consecutive_items: false,
+        }
+    }
+
+    #[test]
+    fn should_recommend_most_popular_items() {
+        let training_data = vec![(1, 10, 100), (1, 11, 101), (2, 10, 102), (3, 12, 103), (3, 10, 104), (3, 11, 105)];
+        let undertest = PopularityIndex::new(&training_data, 0);
+        assert_eq!(vec![10, 11, 12], recommend_items(&undertest, &[], &options(5)));
+        assert_eq!(vec![11], recommend_items(&undertest, &[10], &options(1)));
+        assert_eq!(3, undertest.count(&10));
+    }
+
+    #[test]
+    fn should_skip_items_that_are_not_for_sale_with_business_logic() {
+        let training_data = vec![(1, 10, 100), (1, 11, 101), (2, 10, 102), (3, 12, 103), (3, 10, 104)];
+        let mut undertest = PopularityIndex::new(&training_data, 0);
+        Arc::get_mut(&mut undertest.item_to_product_attributes)
+            .unwrap()
+            .get_mut(&10)
+            .unwrap()
+            .is_for_sale = false;
+        let business_options = RecommendOptions {
+            enable_business_logic: true,
+            ..options(5)
+        };
+        assert_eq!(vec![11, 12], recommend_items(&undertest, &[], &business_options));
+        assert_eq!(vec![10, 11, 12], recommend_items(&undertest, &[], &options(5)));
+
+        let live_index = LivePopularityIndex::new(undertest);
+        live_index.add_event(13, 200);
+        live_index.refresh();
+        // live items without product attributes are skipped as well
+        assert_eq!(vec![11, 12], recommend_items(&live_index, &[], &business_options));
+    }
+
+    #[test]
+    fn should_only_build_the_lazy_index_on_first_use() {
+        let qty_builds = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let builds = qty_builds.clone();
+        let undertest = LazyLivePopularityIndex::new(move || {
+            builds.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            PopularityIndex::new(&[(1, 10, 100), (2, 10, 101), (2, 11, 102)], 0)
+        });
+        assert!(!undertest.is_built());
+        assert_eq!(0, qty_builds.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(vec![10, 11], recommend_items(&undertest, &[], &options(5)));
+        undertest.get().add_event(11, 200);
+        undertest.get().add_event(11, 201);
+        undertest.get().refresh();
+        assert_eq!(vec![11, 10], recommend_items(&undertest, &[], &options(5)));
+        assert_eq!(1, qty_builds.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[test]
+    fn should_only_count_events_within_the_window() {
+        let training_data = vec![(1, 10, 100), (1, 10, 101), (2, 11, 500), (3, 12, 600)];
+        let undertest = PopularityIndex::new(&training_data, 200);
+        assert_eq!(0, undertest.count(&10));
+        assert_eq!(vec![11, 12], recommend_items(&undertest, &[], &options(5)));
+    }
+
+    #[test]
+    fn should_forget_events_outside_the_window_when_adding_live_events() {
+        let training_data = vec![(1, 10, 100), (2, 11, 150)];
+        let mut undertest = PopularityIndex::new(&training_data, 100);
+        undertest.add_event(12, 220);
+        assert_eq!(0, undertest.count(&10));
+        assert_eq!(1, undertest.count(&11));
+        assert_eq!(1, undertest.count(&12));
+    }
+
+    #[test]
+    fn should_rank_live_events_of_concurrent_requests() {
+        let training_data = vec![(1, 10, 100), (1, 10, 101), (2, 11, 102)];
+        let undertest = Arc::new(LivePopularityIndex::new(PopularityIndex::new(&training_data, 0)));
+        let requests = (0..4)
+            .map(|_| {
+                let index = undertest.clone();
+                thread::spawn(move || {
+                    for time in 0..500 {
+                        index.add_event(12, 200 + time);
+                        assert!(!recommend_items(index.as_ref(), &[], &options(3)).is_empty());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for request in requests {
+            request.join().unwrap();
+        }
+        undertest.refresh();
+        let recommendations = undertest.recommend(&[], &options(3));
+        assert_eq!(12, recommendations[0].id);
+        assert!((2000.0 - recommendations[0].score).abs() < f64::EPSILON);
+        assert_eq!(vec![12, 10, 11], recommend_items(undertest.as_ref(), &[], &options(3)));
+    }
+}
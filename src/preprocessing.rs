@@ -0,0 +1,300 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::io::{ItemId, Time, TrainingSessionId};
+
+/// A raw interaction, keyed by a session id or, for event streams, by a user id.
+pub type RawInteraction = (u64, ItemId, Time);
+
+/// The filters and transformations of `preprocess`, in the order in which they are applied.
+#[derive(Debug, Clone)]
+pub struct PreprocessingOptions {
+    /// Splits the event stream of a user into sessions when the gap between two events is larger,
+    /// `0` when the raw interactions are already keyed by session.
+    pub session_gap_in_secs: usize,
+    pub dedup_consecutive_items: bool,
+    /// Items with fewer interactions are removed.
+    pub min_item_support: usize,
+    /// Sessions with fewer interactions are removed, before and after the item support filter.
+    pub min_session_length: usize,
+    /// Sessions with more interactions are removed, like bots and crawlers. `0` for no maximum.
+    pub max_session_length: usize,
+    /// Replaces the session and item ids by dense ids starting at 1.
+    pub remap_ids: bool,
+}
+
+/// What `preprocess` removed, to check that the filters are not too strict.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct PreprocessingSummary {
+    pub qty_unparsable_rows: usize,
+    pub qty_input_interactions: usize,
+    pub qty_sessions_after_sessionization: usize,
+    pub qty_removed_consecutive_duplicates: usize,
+    pub qty_removed_short_sessions: usize,
+    pub qty_removed_long_sessions: usize,
+    pub qty_removed_rare_items: usize,
+    pub qty_removed_sessions_without_support: usize,
+    pub qty_output_interactions: usize,
+    pub qty_output_sessions: usize,
+    pub qty_output_items: usize,
+}
+
+impl PreprocessingSummary {
+    /// One `name: value` line per count.
+    pub fn lines(&self) -> Vec<String> {
+        let counts = [
+            ("unparsable rows", self.qty_unparsable_rows),
+            ("input interactions", self.qty_input_interactions),
+            ("sessions after sessionization", self.qty_sessions_after_sessionization),
+            ("removed consecutive duplicates", self.qty_removed_consecutive_duplicates),
+            ("removed short sessions", self.qty_removed_short_sessions),
+            ("removed long sessions", self.qty_removed_long_sessions),
+            ("removed interactions with rare items", self.qty_removed_rare_items),
+            ("removed short sessions after the item filter", self.qty_removed_sessions_without_support),
+            ("output interactions", self.qty_output_interactions),
+            ("output sessions", self.qty_output_sessions),
+            ("output items", self.qty_output_items),
+        ];
+        counts
+            .iter()
+            .map(|(name, count)| format!("{}: {}", name, count))
+            .collect()
+    }
+}
+
+/// The result of `preprocess`. The id mapping holds `(new id, original id)` pairs of the items
+/// when the ids are remapped.
+pub struct Preprocessed {
+    pub interactions: Vec<(TrainingSessionId, ItemId, Time)>,
+    pub item_id_mapping: Vec<(ItemId, ItemId)>,
+    pub summary: PreprocessingSummary,
+}
+
+/// Reads tab or space separated `key, item id, time` rows after a header, like `io::read_training_data`,
+/// but counts the rows that cannot be parsed instead of failing or skipping them silently.
+pub fn read_raw_interactions(path: &str) -> io::Result<(Vec<RawInteraction>, usize)> {
+    let mut interactions = Vec::new();
+    let mut qty_unparsable_rows = 0;
+    for line in BufReader::new(File::open(path)?).lines().skip(1) {
+        let line = line?;
+        let parts = line.split_whitespace().take(3).collect_vec();
+        let parsed = match parts.as_slice() {
+            [key, item_id, time] => match (key.parse::<u64>(), item_id.parse::<ItemId>(), time.parse::<f64>()) {
+                (Ok(key), Ok(item_id), Ok(time)) if time >= 0.0 => Some((key, item_id, time.round() as Time)),
+                _ => None,
+            },
+            _ => None,
+        };
+        match parsed {
+            Some(interaction) => interactions.push(interaction),
+            None => qty_unparsable_rows += 1,
+        }
+    }
+    Ok((interactions, qty_unparsable_rows))
+}
+
+/// Cleans raw interactions with the filters of `options`, the sessions of the result are ordered by
+/// id and the interactions of a session by time.
+pub fn preprocess(raw_interactions: Vec<RawInteraction>, options: &PreprocessingOptions) -> Result<Preprocessed, String> {
+    let mut summary = PreprocessingSummary {
+        qty_input_interactions: raw_interactions.len(),
+        ..Default::default()
+    };
+
+    let mut sessions = sessionize(raw_interactions, options.session_gap_in_secs);
+    summary.qty_sessions_after_sessionization = sessions.len();
+
+    if options.dedup_consecutive_items {
+        for (_session_id, interactions) in sessions.iter_mut() {
+            let qty_before = interactions.len();
+            interactions.dedup_by_key(|(item_id, _time)| *item_id);
+            summary.qty_removed_consecutive_duplicates += qty_before - interactions.len();
+        }
+    }
+
+    let qty_before = sessions.len();
+    sessions.retain(|(_session_id, interactions)| interactions.len() >= options.min_session_length);
+    summary.qty_removed_short_sessions = qty_before - sessions.len();
+    if options.max_session_length > 0 {
+        let qty_before = sessions.len();
+        sessions.retain(|(_session_id, interactions)| interactions.len() <= options.max_session_length);
+        summary.qty_removed_long_sessions = qty_before - sessions.len();
+    }
+
+    let item_support = sessions
+        .iter()
+        .flat_map(|(_session_id, interactions)| interactions.iter().map(|(item_id, _time)| *item_id))
+        .counts();
+    for (_session_id, interactions) in sessions.iter_mut() {
+        let qty_before = interactions.len();
+        interactions.retain(|(item_id, _time)| item_support[item_id] >= options.min_item_support);
+        summary.qty_removed_rare_items += qty_before - interactions.len();
+        if options.dedup_consecutive_items {
+            // Removing an item can make its neighbors consecutive repeats.
+            let qty_before = interactions.len();
+            interactions.dedup_by_key(|(item_id, _time)| *item_id);
+            summary.qty_removed_consecutive_duplicates += qty_before - interactions.len();
+        }
+    }
+    let qty_before = sessions.len();
+    sessions.retain(|(_session_id, interactions)| interactions.len() >= options.min_session_length);
+    summary.qty_removed_sessions_without_support = qty_before - sessions.len();
+
+    let mut item_id_mapping = Vec::new();
+    if options.remap_ids {
+        let mut item_ids: HashMap<ItemId, ItemId> = HashMap::new();
+        for (index, (session_id, interactions)) in sessions.iter_mut().enumerate() {
+            *session_id = index as u64 + 1;
+            for (item_id, _time) in interactions.iter_mut() {
+                let next_id = item_ids.len() as ItemId + 1;
+                let new_id = *item_ids.entry(*item_id).or_insert(next_id);
+                if new_id == next_id {
+                    item_id_mapping.push((new_id, *item_id));
+                }
+                *item_id = new_id;
+            }
+        }
+    }
+
+    let mut interactions = Vec::with_capacity(sessions.iter().map(|(_, interactions)| interactions.len()).sum());
+    for (session_id, session_interactions) in sessions.iter() {
+        let session_id = TrainingSessionId::try_from(*session_id)
+            .map_err(|_| format!("Session id {} is too large, remap the ids", session_id))?;
+        for (item_id, time) in session_interactions.iter() {
+            interactions.push((session_id, *item_id, *time));
+        }
+    }
+    summary.qty_output_interactions = interactions.len();
+    summary.qty_output_sessions = sessions.len();
+    summary.qty_output_items = interactions.iter().map(|(_, item_id, _)| item_id).unique().count();
+
+    Ok(Preprocessed {
+        interactions,
+        item_id_mapping,
+        summary,
+    })
+}
+
+/// Groups the interactions by key and orders them by time. With a `session_gap_in_secs`, the keys are
+/// users whose events are split into sessions at larger gaps, numbered from 1 in the order of the users.
+fn sessionize(raw_interactions: Vec<RawInteraction>, session_gap_in_secs: usize) -> Vec<(u64, Vec<(ItemId, Time)>)> {
+    let groups = raw_interactions
+        .into_iter()
+        .map(|(key, item_id, time)| (key, (item_id, time)))
+        .into_group_map()
+        .into_iter()
+        .sorted_by_key(|(key, _interactions)| *key)
+        .map(|(key, mut interactions)| {
+            interactions.sort_by_key(|(_item_id, time)| *time);
+            (key, interactions)
+        });
+    if session_gap_in_secs == 0 {
+        return groups.collect();
+    }
+    let mut sessions = Vec::new();
+    for (_user_id, interactions) in groups {
+        let mut session: Vec<(ItemId, Time)> = Vec::new();
+        for (item_id, time) in interactions {
+            if let Some((_, last_time)) = session.last() {
+                if time - last_time > session_gap_in_secs {
+                    sessions.push((sessions.len() as u64 + 1, std::mem::take(&mut session)));
+                }
+            }
+            session.push((item_id, time));
+        }
+        sessions.push((sessions.len() as u64 + 1, session));
+    }
+    sessions
+}
+
+#[cfg(test)]
+mod preprocessing_test {
+    use super::*;
+
+    fn options() -> PreprocessingOptions {
+        PreprocessingOptions {
+            session_gap_in_secs: 0,
+            dedup_consecutive_items: false,
+            min_item_support: 1,
+            min_session_length: 1,
+            max_session_length: 0,
+            remap_ids: false,
+        }
+    }
+
+    #[test]
+    fn should_split_user_streams_at_inactivity_gaps() {
+        let raw = vec![(7, 1, 0), (7, 2, 10), (7, 3, 1000), (5, 4, 0)];
+        let preprocessed = preprocess(
+            raw,
+            &PreprocessingOptions {
+                session_gap_in_secs: 100,
+                ..options()
+            },
+        )
+        .unwrap();
+        assert_eq!(vec![(1, 4, 0), (2, 1, 0), (2, 2, 10), (3, 3, 1000)], preprocessed.interactions);
+        assert_eq!(3, preprocessed.summary.qty_sessions_after_sessionization);
+    }
+
+    #[test]
+    fn should_apply_the_filters_and_count_the_removals() {
+        let raw = vec![
+            (1, 10, 0),
+            (1, 10, 1),
+            (1, 11, 2),
+            (2, 10, 0),
+            (2, 11, 1),
+            (2, 12, 2),
+            (3, 11, 0),
+            (3, 10, 1),
+            (3, 11, 2),
+            (3, 10, 3),
+            (4, 10, 0),
+            (5, 13, 0),
+            (5, 10, 1),
+        ];
+        let preprocessed = preprocess(
+            raw,
+            &PreprocessingOptions {
+                dedup_consecutive_items: true,
+                min_item_support: 2,
+                min_session_length: 2,
+                max_session_length: 3,
+                ..options()
+            },
+        )
+        .unwrap();
+        assert_eq!(vec![(1, 10, 0), (1, 11, 2), (2, 10, 0), (2, 11, 1)], preprocessed.interactions);
+        let summary = preprocessed.summary;
+        assert_eq!(1, summary.qty_removed_consecutive_duplicates);
+        assert_eq!(1, summary.qty_removed_short_sessions);
+        assert_eq!(1, summary.qty_removed_long_sessions);
+        assert_eq!(2, summary.qty_removed_rare_items);
+        assert_eq!(1, summary.qty_removed_sessions_without_support);
+        assert_eq!(2, summary.qty_output_sessions);
+    }
+
+    #[test]
+    fn should_remap_ids() {
+        let raw = vec![(1000, 99, 0), (1000, 42, 1), (2000, 42, 0)];
+        let preprocessed = preprocess(
+            raw,
+            &PreprocessingOptions {
+                remap_ids: true,
+                ..options()
+            },
+        )
+        .unwrap();
+        assert_eq!(vec![(1, 1, 0), (1, 2, 1), (2, 2, 0)], preprocessed.interactions);
+        assert_eq!(vec![(1, 99), (2, 42)], preprocessed.item_id_mapping);
+
+        let too_large = preprocess(vec![(u64::MAX, 1, 0)], &options());
+        assert!(too_large.is_err());
+    }
+}
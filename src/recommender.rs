@@ -0,0 +1,295 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::config::{EnsembleConfig, ModelConfig};
+use crate::ensemble::{parse_members, Ensemble, ScoreNormalization};
+use crate::io::ItemId;
+use crate::rules::{RuleKind, RulesIndex};
+use crate::vmisknn::vmis_index::VMISIndex;
+use crate::vmisknn::vsknn_index::VSkNNIndex;
+use crate::vmisknn::{ItemScore, PredictError};
+
+/// Request-time parameters shared by all recommenders. Models ignore the parameters that do not apply to them.
+#[derive(Debug, Clone)]
+pub struct RecommendOptions {
+    /// The top `k` scored neighbor sessions used for item scoring.
+    pub neighborhood_size_k: usize,
+    /// The `m` most recent neighbor sessions considered per item.
+    pub m_most_recent_sessions: usize,
+    /// The maximum amount of recommended items.
+    pub num_items_to_recommend: usize,
+    pub enable_business_logic: bool,
+    /// Collapse repeated consecutive interactions with the same item before recommending.
+    pub dedup_consecutive_items: bool,
+}
+
+/// A session-based recommender, so alternative algorithms can be plugged into serving and evaluation.
+pub trait Recommender: Send + Sync {
+    /// Returns up to `options.num_items_to_recommend` scored items for the evolving session,
+    /// the highest score first. The most recent item of the session is never recommended.
+    fn recommend(&self, evolving_session: &[ItemId], options: &RecommendOptions) -> Vec<ItemScore>;
+
+    /// Like `recommend`, but reports why the evolving session cannot be scored instead of
+    /// recommending no items, for recommenders that can fail.
+    fn try_recommend(
+        &self,
+        evolving_session: &[ItemId],
+        options: &RecommendOptions,
+    ) -> Result<Vec<ItemScore>, PredictError> {
+        Ok(self.recommend(evolving_session, options))
+    }
+}
+
+/// The recommendations of `try_recommend`, or no items after logging why the session cannot be scored.
+pub(crate) fn recommended_or_logged(recommendations: Result<Vec<ItemScore>, PredictError>) -> Vec<ItemScore> {
+    recommendations.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        Vec::new()
+    })
+}
+
+/// Convenience to get only the item ids of the recommendations.
+pub fn recommend_items<R: Recommender + ?Sized>(
+    recommender: &R,
+    evolving_session: &[ItemId],
+    options: &RecommendOptions,
+) -> Vec<ItemId> {
+    recommender
+        .recommend(evolving_session, options)
+        .iter()
+        .map(|scored| scored.id)
+        .collect()
+}
+
+/// The session-based kNN algorithm that serves the recommendations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// VMIS-kNN, the default and the fastest.
+    VMISkNN,
+    /// The reference V-SkNN implementation, to validate the accuracy of VMIS-kNN.
+    VSkNN,
+    /// Sequential rules on the most recent item.
+    SequentialRules,
+    /// Association rules on the most recent item.
+    AssociationRules,
+    /// The most popular items, regardless of the evolving session.
+    Popularity,
+    /// A weighted blend of other algorithms, configured in the `[ensemble]` section.
+    Ensemble,
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "vmis_knn" => Ok(Algorithm::VMISkNN),
+            "vs_knn" => Ok(Algorithm::VSkNN),
+            "sr" => Ok(Algorithm::SequentialRules),
+            "ar" => Ok(Algorithm::AssociationRules),
+            "popularity" => Ok(Algorithm::Popularity),
+            "ensemble" => Ok(Algorithm::Ensemble),
+            _ => Err(format!("Unknown algorithm: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Algorithm::VMISkNN => "vmis_knn",
+            Algorithm::VSkNN => "vs_knn",
+            Algorithm::SequentialRules => "sr",
+            Algorithm::AssociationRules => "ar",
+            Algorithm::Popularity => "popularity",
+            Algorithm::Ensemble => "ensemble",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Algorithm {
+    /// The rules are mined from a csv file, an avro index lacks the order of the items within a session.
+    fn requires_csv_training_data(&self) -> bool {
+        matches!(self, Algorithm::SequentialRules | Algorithm::AssociationRules)
+    }
+}
+
+/// Checks that the algorithm, or every member of an ensemble, can be trained from the data at
+/// `training_data_path`, so a misconfiguration fails at startup before any model is loaded.
+pub fn validate_training_data(
+    algorithm: Algorithm,
+    ensemble_config: &EnsembleConfig,
+    training_data_path: &str,
+) -> Result<(), String> {
+    let algorithms = trained_algorithms(algorithm, ensemble_config)?;
+    match algorithms.iter().find(|algorithm| algorithm.requires_csv_training_data()) {
+        Some(algorithm) if !Path::new(training_data_path).is_file() => Err(format!(
+            "The algorithm {} requires a csv file as training_data_path, not the avro index in {}",
+            algorithm, training_data_path
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the algorithm, or a member of an ensemble, recommends with the popularity model.
+pub fn uses_popularity(algorithm: Algorithm, ensemble_config: &EnsembleConfig) -> Result<bool, String> {
+    Ok(trained_algorithms(algorithm, ensemble_config)?.contains(&Algorithm::Popularity))
+}
+
+fn trained_algorithms(
+    algorithm: Algorithm,
+    ensemble_config: &EnsembleConfig,
+) -> Result<Vec<Algorithm>, String> {
+    match algorithm {
+        Algorithm::Ensemble => Ok(parse_members(&ensemble_config.members)?
+            .into_iter()
+            .map(|(member_algorithm, _weight)| member_algorithm)
+            .collect()),
+        _ => Ok(vec![algorithm]),
+    }
+}
+
+/// Creates the recommender for the algorithm. V-SkNN is trained on the sessions in `vmis_index`,
+/// so both kNN algorithms see the same training data regardless of its format. The rules need the
+/// order of the items within a session and are mined from the csv file at `training_data_path`.
+/// The popularity model is passed in, serving shares it with `/v1/popular` and builds it lazily.
+pub fn create_recommender(
+    algorithm: Algorithm,
+    vmis_index: Arc<VMISIndex>,
+    popularity_index: Arc<dyn Recommender>,
+    training_data_path: &str,
+    model_config: &ModelConfig,
+    ensemble_config: &EnsembleConfig,
+) -> Arc<dyn Recommender> {
+    let m_most_recent_sessions = model_config.m_most_recent_sessions;
+    let idf_weighting = model_config.idf_weighting as f64;
+    match algorithm {
+        Algorithm::VMISkNN => vmis_index,
+        Algorithm::VSkNN => Arc::new(VSkNNIndex::new(
+            vmis_index.session_interactions(),
+            m_most_recent_sessions,
+            usize::MAX,
+            idf_weighting,
+        )),
+        Algorithm::SequentialRules => Arc::new(RulesIndex::new_from_csv(
+            training_data_path,
+            RuleKind::Sequential,
+            model_config.rules_max_steps,
+        )),
+        Algorithm::AssociationRules => Arc::new(RulesIndex::new_from_csv(
+            training_data_path,
+            RuleKind::Association,
+            model_config.rules_max_steps,
+        )),
+        Algorithm::Popularity => popularity_index,
+        Algorithm::Ensemble => {
+            let normalization: ScoreNormalization = ensemble_config
+                .normalization
+                .parse()
+                .unwrap_or_else(|err| panic!("{}", err));
+            let members = parse_members(&ensemble_config.members)
+                .unwrap_or_else(|err| panic!("{}", err))
+                .into_iter()
+                .map(|(member_algorithm, weight)| {
+                    let member = create_recommender(
+                        member_algorithm,
+                        vmis_index.clone(),
+                        popularity_index.clone(),
+                        training_data_path,
+                        model_config,
+                        ensemble_config,
+                    );
+                    (member, weight)
+                })
+                .collect();
+            Arc::new(Ensemble::new(members, normalization))
+        }
+    }
+}
+
+#[cfg(test)]
+mod recommender_test {
+    use super::*;
+    use crate::popularity::PopularityIndex;
+
+    #[test]
+    fn should_parse_algorithm_names() {
+        for name in ["vmis_knn", "vs_knn", "sr", "ar", "popularity", "ensemble"] {
+            let algorithm: Algorithm = name.parse().unwrap();
+            assert_eq!(name, algorithm.to_string());
+        }
+        assert!("sknn".parse::<Algorithm>().is_err());
+    }
+
+    #[test]
+    fn should_reject_rules_on_an_avro_index() {
+        let ensemble_config = EnsembleConfig {
+            members: "vmis_knn:0.8,sr:0.2".to_string(),
+            normalization: "min_max".to_string(),
+        };
+        let avro_index_dir = std::env::temp_dir();
+        let avro_index_dir = avro_index_dir.to_str().unwrap();
+        assert!(validate_training_data(Algorithm::VMISkNN, &ensemble_config, avro_index_dir).is_ok());
+        assert!(validate_training_data(Algorithm::AssociationRules, &ensemble_config, avro_index_dir).is_err());
+        assert!(validate_training_data(Algorithm::Ensemble, &ensemble_config, avro_index_dir).is_err());
+        assert!(validate_training_data(Algorithm::SequentialRules, &ensemble_config, "Cargo.toml").is_ok());
+    }
+
+    #[test]
+    fn should_recommend_the_same_items_with_both_algorithms() {
+        let historical_sessions = vec![vec![1, 2, 3], vec![1, 2], vec![2, 4], vec![5, 6]];
+        let vmis_index = Arc::new(VMISIndex::new_from_sessions(historical_sessions, vec![10, 20, 30, 40], 500));
+        let popularity_index = Arc::new(PopularityIndex::new(&vmis_index.session_interactions(), 0));
+        let options = RecommendOptions {
+            neighborhood_size_k: 500,
+            m_most_recent_sessions: 500,
+            num_items_to_recommend: 20,
+            enable_business_logic: true,
+            dedup_consecutive_items: false,
+        };
+        let ensemble_config = EnsembleConfig {
+            members: "vmis_knn:1".to_string(),
+            normalization: "min_max".to_string(),
+        };
+        let model_config = ModelConfig {
+            m_most_recent_sessions: 500,
+            neighborhood_size_k: 500,
+            num_items_to_recommend: 20,
+            max_items_in_session: 2,
+            idf_weighting: 1,
+            max_items_in_user_history: 20,
+            algorithm: "vmis_knn".to_string(),
+            rules_max_steps: 10,
+            dedup_consecutive_items: false,
+            neighbor_similarity: "dot_product".to_string(),
+        };
+        let create = |algorithm| {
+            create_recommender(
+                algorithm,
+                vmis_index.clone(),
+                popularity_index.clone(),
+                "unused",
+                &model_config,
+                &ensemble_config,
+            )
+        };
+        let vmis_knn = create(Algorithm::VMISkNN);
+        let vs_knn = create(Algorithm::VSkNN);
+        let ensemble = create(Algorithm::Ensemble);
+
+        let mut vmis_items = recommend_items(vmis_knn.as_ref(), &[1, 2], &options);
+        let mut vs_items = recommend_items(vs_knn.as_ref(), &[1, 2], &options);
+        vmis_items.sort_unstable();
+        vs_items.sort_unstable();
+
+        assert_eq!(vec![1, 3, 4], vmis_items);
+        assert_eq!(vmis_items, vs_items);
+        assert_eq!(
+            recommend_items(vmis_knn.as_ref(), &[1, 2], &options),
+            recommend_items(ensemble.as_ref(), &[1, 2], &options)
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use hashbrown::HashMap;
+use itertools::Itertools;
+
+use crate::io::{ItemId, Time, TrainingSessionId};
+use crate::recommender::{RecommendOptions, Recommender};
+use crate::vmisknn::passes_business_rules;
+use crate::vmisknn::vmis_index::ProductAttributes;
+use crate::vmisknn::ItemScore;
+
+// Amount of rules kept per item, requests only need the strongest ones.
+const MAX_RULES_PER_ITEM: usize = 1000;
+
+/// How item pairs within a training session become rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    /// Sequential rules: an item predicts the items that followed it, weighted by `1 / steps`
+    /// between both items.
+    Sequential,
+    /// Association rules: two items predict each other regardless of their order, every
+    /// co-occurrence within the window weighs `1`.
+    Association,
+}
+
+/// Item-to-item rules mined from the co-occurrences within training sessions. The
+/// recommendations only depend on the most recent item of the evolving session.
+pub struct RulesIndex {
+    // Per item the rules ordered by descending weight.
+    rules: HashMap<ItemId, Vec<(ItemId, f64)>>,
+    item_to_product_attributes: HashMap<ItemId, ProductAttributes>,
+}
+
+impl RulesIndex {
+    /// Creates an index from a csv file with training data, see `io::read_training_data`.
+    pub fn new_from_csv(path_to_training: &str, kind: RuleKind, max_steps: usize) -> Self {
+        let interactions = crate::io::read_training_data(path_to_training);
+        RulesIndex::new(&interactions, kind, max_steps)
+    }
+
+    /// Mines the rules from the training interactions.
+    ///
+    /// # Arguments
+    ///
+    /// * `interactions` - the training interactions, the time orders the items within a session.
+    /// * `kind` - sequential or association rules.
+    /// * `max_steps` - only items at most this many positions apart in a session form a rule.
+    pub fn new(
+        interactions: &[(TrainingSessionId, ItemId, Time)],
+        kind: RuleKind,
+        max_steps: usize,
+    ) -> Self {
+        let sessions = interactions
+            .iter()
+            .map(|(session_id, item_id, time)| (*session_id, (*time, *item_id)))
+            .into_group_map();
+
+        let mut rule_weights: HashMap<ItemId, HashMap<ItemId, f64>> = HashMap::new();
+        for (_session_id, mut session_events) in sessions.into_iter() {
+            session_events.sort_unstable();
+            let session_items: Vec<ItemId> = session_events
+                .into_iter()
+                .map(|(_time, item_id)| item_id)
+                .dedup()
+                .collect();
+            for (pos, item_id) in session_items.iter().enumerate() {
+                let last_pos = session_items.len().min(pos + max_steps + 1);
+                for (steps, other_item_id) in session_items[pos + 1..last_pos].iter().enumerate() {
+                    if item_id == other_item_id {
+                        continue;
+                    }
+                    match kind {
+                        RuleKind::Sequential => {
+                            let weight = 1.0 / (steps + 1) as f64;
+                            add_weight(&mut rule_weights, *item_id, *other_item_id, weight);
+                        }
+                        RuleKind::Association => {
+                            add_weight(&mut rule_weights, *item_id, *other_item_id, 1.0);
+                            add_weight(&mut rule_weights, *other_item_id, *item_id, 1.0);
+                        }
+                    }
+                }
+            }
+        }
+
+        let rules = rule_weights
+            .into_iter()
+            .map(|(item_id, weights)| {
+                let mut ranked: Vec<(ItemId, f64)> = weights.into_iter().collect();
+                // Ties are broken by item id to keep the ranking reproducible.
+                ranked.sort_unstable_by(|(item_a, weight_a), (item_b, weight_b)| {
+                    weight_b.partial_cmp(weight_a).unwrap().then(item_a.cmp(item_b))
+                });
+                ranked.truncate(MAX_RULES_PER_ITEM);
+                (item_id, ranked)
+            })
+            .collect();
+
+        // Product attributes are not part of the training data yet, identical to `VMISIndex`.
+        let item_to_product_attributes = interactions
+            .iter()
+            .map(|(_session_id, item_id, _time)| {
+                let attributes = ProductAttributes {
+                    is_adult: false,
+                    is_for_sale: true,
+                };
+                (*item_id, attributes)
+            })
+            .collect();
+
+        RulesIndex {
+            rules,
+            item_to_product_attributes,
+        }
+    }
+}
+
+fn add_weight(
+    rule_weights: &mut HashMap<ItemId, HashMap<ItemId, f64>>,
+    from_item_id: ItemId,
+    to_item_id: ItemId,
+    weight: f64,
+) {
+    *rule_weights
+        .entry(from_item_id)
+        .or_default()
+        .entry(to_item_id)
+        .or_insert(0.0) += weight;
+}
+
+impl Recommender for RulesIndex {
+    fn recommend(&self, evolving_session: &[ItemId], options: &RecommendOptions) -> Vec<ItemScore> {
+        let most_recent_item = match evolving_session.last() {
+            Some(most_recent_item) => most_recent_item,
+            None => return Vec::new(),
+        };
+        let rules = match self.rules.get(most_recent_item) {
+            Some(rules) => rules,
+            None => return Vec::new(),
+        };
+        let current_item_attribs = self.item_to_product_attributes.get(most_recent_item);
+        rules
+            .iter()
+            .filter(|(item_id, _weight)| {
+                !options.enable_business_logic
+                    || passes_business_rules(
+                        current_item_attribs,
+                        self.item_to_product_attributes.get(item_id),
+                    )
+            })
+            .take(options.num_items_to_recommend)
+            .map(|(item_id, weight)| ItemScore::new(*item_id, *weight))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod rules_test {
+    use super::*;
+    use crate::recommender::recommend_items;
+
+    fn options() -> RecommendOptions {
+        RecommendOptions {
+            neighborhood_size_k: 500,
+            m_most_recent_sessions: 500,
+            num_items_to_recommend: 20,
+            enable_business_logic: true,
+            dedup_consecutive_items: false,
+        }
+    }
+
+    fn training_data() -> Vec<(TrainingSessionId, ItemId, Time)> {
+        vec![
+            (1, 10, 100),
+            (1, 11, 101),
+            (1, 12, 102),
+            (2, 10, 200),
+            (2, 12, 201),
+            (3, 13, 300),
+            (3, 10, 301),
+        ]
+    }
+
+    #[test]
+    fn should_recommend_following_items_with_sequential_rules() {
+        let undertest = RulesIndex::new(&training_data(), RuleKind::Sequential, 10);
+        let recommendations = undertest.recommend(&[10], &options());
+        // 12 follows 10 in two sessions, 1/2 + 1/1
+        assert_eq!(12, recommendations[0].id);
+        assert_eq!(1.5, recommendations[0].score);
+        assert_eq!(vec![12, 11], recommend_items(&undertest, &[10], &options()));
+        assert!(recommend_items(&undertest, &[12], &options()).is_empty());
+    }
+
+    #[test]
+    fn should_recommend_co_occurring_items_with_association_rules() {
+        let undertest = RulesIndex::new(&training_data(), RuleKind::Association, 10);
+        assert_eq!(vec![12, 11, 13], recommend_items(&undertest, &[10], &options()));
+        assert_eq!(vec![10, 11], recommend_items(&undertest, &[12], &options()));
+    }
+
+    #[test]
+    fn should_only_mine_rules_within_the_window() {
+        let undertest = RulesIndex::new(&training_data(), RuleKind::Sequential, 1);
+        assert_eq!(vec![11, 12], recommend_items(&undertest, &[10], &options()));
+        assert!(recommend_items(&undertest, &[99], &options()).is_empty());
+    }
+}
@@ -1,8 +1,13 @@
-use rocksdb::{DB, Options};
+use rocksdb::{IteratorMode, WriteBatch, DB, Options};
 use bincode;
+use std::fmt;
 use std::time::{Duration, SystemTime};
 use crate::io::ItemId;
 use serde::{Serialize, Deserialize};
+use uuid::Builder;
+use itertools::Itertools;
+
+pub mod user_profiles;
 
 pub struct RocksDBSessionStore {
     rocks_db: DB,
@@ -10,8 +15,47 @@ pub struct RocksDBSessionStore {
 }
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DBValue {
-    session_items: Vec<ItemId>,
-    epoch_secs: u64,
+    pub session_items: Vec<ItemId>,
+    pub epoch_secs: u64,
+}
+
+/// Derives the key of an evolving session from the session_id as it is received from the visitor.
+pub fn evolving_session_id(session_id: &str) -> u128 {
+    let session_id_digest = md5::compute(session_id);
+    Builder::from_bytes(session_id_digest.0).build().as_u128()
+}
+
+/// Failures of the session store. A failing read or write must never take down the
+/// service, callers are expected to degrade gracefully and report these.
+#[derive(Debug)]
+pub enum SessionStoreError {
+    Database(rocksdb::Error),
+    Serialization(bincode::Error),
+}
+
+impl fmt::Display for SessionStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionStoreError::Database(err) => write!(f, "session store database error: {}", err),
+            SessionStoreError::Serialization(err) => {
+                write!(f, "session store serialization error: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SessionStoreError {}
+
+impl From<rocksdb::Error> for SessionStoreError {
+    fn from(err: rocksdb::Error) -> Self {
+        SessionStoreError::Database(err)
+    }
+}
+
+impl From<bincode::Error> for SessionStoreError {
+    fn from(err: bincode::Error) -> Self {
+        SessionStoreError::Serialization(err)
+    }
 }
 
 
@@ -34,17 +78,18 @@ impl RocksDBSessionStore {
         Self { rocks_db, max_session_idle_duration_in_seconds: 60 * 20  }
     }
 
-    pub fn get_session_items(&self, evolving_session_id: &u128) -> Vec<u64> {
+    pub fn get_session_items(&self, evolving_session_id: &u128) -> Result<Vec<u64>, SessionStoreError> {
         let serialized_session_id =
-            bincode::serialize(&evolving_session_id).unwrap();
+            bincode::serialize(&evolving_session_id)?;
 
-        let bytes = self.rocks_db.get(&serialized_session_id).unwrap();
+        let bytes = self.rocks_db.get(&serialized_session_id)?;
 
         let session_items: Vec<u64> = match bytes {
             Some(bytes) => {
-                let payload:DBValue = bincode::deserialize(&bytes).unwrap();
+                let payload:DBValue = bincode::deserialize(&bytes)?;
                 let now = self.get_seconds_since_epoch();
-                let seconds_since_last_event = now - payload.epoch_secs;
+                // The clock may have moved backwards since the event was stored, treat that as a fresh event.
+                let seconds_since_last_event = now.saturating_sub(payload.epoch_secs);
                 if seconds_since_last_event <= self.max_session_idle_duration_in_seconds {
                     payload.session_items
                 } else {
@@ -53,24 +98,160 @@ impl RocksDBSessionStore {
             }
             None => Vec::new(),
         };
-        session_items
+        Ok(session_items)
     }
 
     pub fn update_session_items(&self, evolving_session_id: &u128,
-                                session_items: &[u64]) {
-        let serialized_session_id =
-            bincode::serialize(evolving_session_id).unwrap();
+                                session_items: &[u64]) -> Result<(), SessionStoreError> {
+        let now = self.get_seconds_since_epoch();
+        self.import_session(evolving_session_id, session_items, now)
+    }
+
+    /// Erases a stored session, returns whether the session was present in the store.
+    pub fn delete_session(&self, evolving_session_id: &u128) -> Result<bool, SessionStoreError> {
+        let serialized_session_id = bincode::serialize(evolving_session_id)?;
+        let existed = self.rocks_db.get(&serialized_session_id)?.is_some();
+        if existed {
+            self.rocks_db.delete(&serialized_session_id)?;
+        }
+        Ok(existed)
+    }
+
+    /// Erases multiple stored sessions in a single atomic write, returns the amount of distinct sessions
+    /// that were present.
+    pub fn delete_sessions(&self, evolving_session_ids: &[u128]) -> Result<usize, SessionStoreError> {
+        let mut batch = WriteBatch::default();
+        let mut qty_existed = 0;
+        for evolving_session_id in evolving_session_ids.iter().unique() {
+            let serialized_session_id = bincode::serialize(evolving_session_id)?;
+            if self.rocks_db.get(&serialized_session_id)?.is_some() {
+                qty_existed += 1;
+            }
+            batch.delete(&serialized_session_id);
+        }
+        self.rocks_db.write(batch)?;
+        Ok(qty_existed)
+    }
+
+    /// Iterates over all stored sessions that have not been idle for longer than the
+    /// maximum session idle duration, as `(evolving_session_id, payload)` tuples.
+    pub fn live_sessions(&self) -> impl Iterator<Item = Result<(u128, DBValue), SessionStoreError>> + '_ {
         let now = self.get_seconds_since_epoch();
+        self.rocks_db
+            .iterator(IteratorMode::Start)
+            .map(|entry| {
+                let (key, value) = entry?;
+                let evolving_session_id: u128 = bincode::deserialize(&key)?;
+                let payload: DBValue = bincode::deserialize(&value)?;
+                Ok((evolving_session_id, payload))
+            })
+            .filter(move |entry| match entry {
+                Ok((_, payload)) => {
+                    now.saturating_sub(payload.epoch_secs) <= self.max_session_idle_duration_in_seconds
+                }
+                Err(_) => true,
+            })
+    }
+
+    /// Stores a session with an explicit time of the last event, used when importing sessions.
+    pub fn import_session(&self, evolving_session_id: &u128, session_items: &[u64],
+                          epoch_secs: u64) -> Result<(), SessionStoreError> {
+        let serialized_session_id = bincode::serialize(evolving_session_id)?;
         let payload = DBValue {
             session_items: Vec::from(session_items),
-            epoch_secs: now,
+            epoch_secs,
         };
-        let bytes = bincode::serialize(&payload).unwrap();
+        let bytes = bincode::serialize(&payload)?;
 
-        let _ = self.rocks_db.put(&serialized_session_id, &bytes).unwrap();
+        self.rocks_db.put(&serialized_session_id, &bytes)?;
+        Ok(())
     }
 
     fn get_seconds_since_epoch(&self) -> u64 {
-        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod sessions_test {
+    use super::*;
+
+    fn temp_store(name: &str) -> RocksDBSessionStore {
+        let path = std::env::temp_dir().join(format!("serenade_{}_{}", name, std::process::id()));
+        RocksDBSessionStore::new(path.to_str().unwrap(), Duration::from_secs(30 * 60))
+    }
+
+    #[test]
+    fn should_store_and_retrieve_session_items() {
+        let undertest = temp_store("store_and_retrieve");
+        let evolving_session_id = 144_u128;
+        undertest.update_session_items(&evolving_session_id, &[1, 2, 3]).unwrap();
+        assert_eq!(vec![1, 2, 3], undertest.get_session_items(&evolving_session_id).unwrap());
+        assert!(undertest.get_session_items(&145_u128).unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_report_corrupt_records_instead_of_panicking() {
+        let undertest = temp_store("corrupt_record");
+        let evolving_session_id = 144_u128;
+        let key = bincode::serialize(&evolving_session_id).unwrap();
+        undertest.rocks_db.put(&key, [1_u8, 2, 3]).unwrap();
+        assert!(undertest.get_session_items(&evolving_session_id).is_err());
+    }
+
+    #[test]
+    fn should_delete_sessions() {
+        let undertest = temp_store("delete_sessions");
+        undertest.update_session_items(&1_u128, &[1, 2]).unwrap();
+        undertest.update_session_items(&2_u128, &[3]).unwrap();
+        undertest.update_session_items(&3_u128, &[4]).unwrap();
+
+        assert!(undertest.delete_session(&1_u128).unwrap());
+        assert!(!undertest.delete_session(&1_u128).unwrap());
+        assert!(undertest.get_session_items(&1_u128).unwrap().is_empty());
+
+        assert_eq!(2, undertest.delete_sessions(&[2_u128, 3_u128, 4_u128]).unwrap());
+        assert!(undertest.get_session_items(&2_u128).unwrap().is_empty());
+        assert!(undertest.get_session_items(&3_u128).unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_count_duplicate_and_missing_sessions_once() {
+        let undertest = temp_store("delete_duplicate_sessions");
+        undertest.update_session_items(&1_u128, &[1, 2]).unwrap();
+        undertest.update_session_items(&2_u128, &[3]).unwrap();
+
+        assert_eq!(2, undertest.delete_sessions(&[1_u128, 1_u128, 2_u128, 5_u128, 5_u128]).unwrap());
+        assert_eq!(0, undertest.delete_sessions(&[1_u128, 2_u128]).unwrap());
+    }
+
+    #[test]
+    fn should_only_list_live_sessions() {
+        let undertest = temp_store("live_sessions");
+        undertest.update_session_items(&1_u128, &[1, 2]).unwrap();
+        undertest.import_session(&2_u128, &[3], 0).unwrap();
+        let live_sessions = undertest
+            .live_sessions()
+            .map(|entry| entry.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(1, live_sessions.len());
+        assert_eq!(1_u128, live_sessions[0].0);
+        assert_eq!(vec![1, 2], live_sessions[0].1.session_items);
+    }
+
+    #[test]
+    fn should_handle_events_stored_in_the_future() {
+        let undertest = temp_store("clock_skew");
+        let evolving_session_id = 144_u128;
+        let key = bincode::serialize(&evolving_session_id).unwrap();
+        let payload = DBValue {
+            session_items: vec![7],
+            epoch_secs: u64::MAX,
+        };
+        undertest.rocks_db.put(&key, bincode::serialize(&payload).unwrap()).unwrap();
+        assert_eq!(vec![7], undertest.get_session_items(&evolving_session_id).unwrap());
     }
 }
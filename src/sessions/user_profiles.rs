@@ -0,0 +1,175 @@
+use rocksdb::{DB, Options};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::io::ItemId;
+use crate::sessions::{evolving_session_id, DBValue, SessionStoreError};
+
+/// Derives the key of a user profile from the user_id, hashed the same way as session ids.
+pub fn user_profile_id(user_id: &str) -> u128 {
+    evolving_session_id(user_id)
+}
+
+// Updates of a user history are serialized by one of this many locks, picked by the user id.
+const QTY_USER_LOCKS: usize = 64;
+
+/// Long-term item history per user. The history outlives the evolving sessions, which are
+/// forgotten after being idle, and lets us personalise the first click of a returning visitor.
+pub struct RocksDBUserProfileStore {
+    rocks_db: DB,
+    max_items_in_user_history: usize,
+    // Concurrent requests of a user read, change and write the same history, they take turns.
+    user_locks: Vec<Mutex<()>>,
+}
+
+impl RocksDBUserProfileStore {
+    pub fn new(database_file: &str, ttl: Duration, max_items_in_user_history: usize) -> Self {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.optimize_for_point_lookup(5000);
+        options.set_allow_mmap_reads(true);
+        options.set_allow_mmap_writes(true);
+
+        let rocks_db = DB::open_with_ttl(&options, database_file, ttl).unwrap();
+
+        let user_locks = (0..QTY_USER_LOCKS).map(|_| Mutex::new(())).collect();
+
+        Self {
+            rocks_db,
+            max_items_in_user_history,
+            user_locks,
+        }
+    }
+
+    fn user_lock(&self, user_id: &u128) -> &Mutex<()> {
+        &self.user_locks[(*user_id % QTY_USER_LOCKS as u128) as usize]
+    }
+
+    /// Returns the item history of the user, the most recent item last.
+    pub fn get_user_items(&self, user_id: &u128) -> Result<Vec<ItemId>, SessionStoreError> {
+        let serialized_user_id = bincode::serialize(user_id)?;
+        match self.rocks_db.get(&serialized_user_id)? {
+            Some(bytes) => {
+                let payload: DBValue = bincode::deserialize(&bytes)?;
+                Ok(payload.session_items)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Appends an item to the history of the user. An item that was seen before moves to the end,
+    /// the oldest items are dropped when the history exceeds its maximum length.
+    pub fn add_user_item(&self, user_id: &u128, item_id: ItemId) -> Result<(), SessionStoreError> {
+        let _user_lock = self.user_lock(user_id).lock().unwrap();
+        let mut user_items = self.get_user_items(user_id)?;
+        if user_items.last() == Some(&item_id) {
+            return Ok(());
+        }
+        user_items.retain(|existing_item_id| *existing_item_id != item_id);
+        user_items.push(item_id);
+        if user_items.len() > self.max_items_in_user_history {
+            let qty_to_remove = user_items.len() - self.max_items_in_user_history;
+            user_items.drain(0..qty_to_remove);
+        }
+
+        let serialized_user_id = bincode::serialize(user_id)?;
+        let payload = DBValue {
+            session_items: user_items,
+            epoch_secs: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+        };
+        let bytes = bincode::serialize(&payload)?;
+        self.rocks_db.put(&serialized_user_id, &bytes)?;
+        Ok(())
+    }
+
+    /// Erases the history of the user, returns whether a history was present in the store.
+    pub fn delete_user_profile(&self, user_id: &u128) -> Result<bool, SessionStoreError> {
+        let _user_lock = self.user_lock(user_id).lock().unwrap();
+        let serialized_user_id = bincode::serialize(user_id)?;
+        let existed = self.rocks_db.get(&serialized_user_id)?.is_some();
+        if existed {
+            self.rocks_db.delete(&serialized_user_id)?;
+        }
+        Ok(existed)
+    }
+}
+
+/// Prepends the most recent items of the user history that are not part of the evolving session,
+/// until the session holds `max_items_in_session` items. Long sessions are returned unchanged.
+/// The prepended items are older than the session items and therefore receive a lower
+/// weight from the position based decay in `find_neighbors`.
+pub fn blend_user_history(
+    session_items: &[ItemId],
+    user_items: &[ItemId],
+    max_items_in_session: usize,
+) -> Vec<ItemId> {
+    let qty_history_items = max_items_in_session.saturating_sub(session_items.len());
+    let mut history_items: Vec<ItemId> = user_items
+        .iter()
+        .rev()
+        .filter(|item_id| !session_items.contains(item_id))
+        .take(qty_history_items)
+        .cloned()
+        .collect();
+    history_items.reverse();
+    history_items.extend_from_slice(session_items);
+    history_items
+}
+
+#[cfg(test)]
+mod user_profiles_test {
+    use super::*;
+
+    #[test]
+    fn should_blend_history_into_short_sessions() {
+        let blended = blend_user_history(&[5], &[1, 2, 3, 5], 3);
+        assert_eq!(vec![2, 3, 5], blended);
+    }
+
+    #[test]
+    fn should_not_blend_history_into_long_sessions() {
+        let blended = blend_user_history(&[6, 7, 8], &[1, 2, 3], 3);
+        assert_eq!(vec![6, 7, 8], blended);
+    }
+
+    #[test]
+    fn should_cap_user_history() {
+        let path = std::env::temp_dir().join(format!("serenade_user_profiles_{}", std::process::id()));
+        let undertest = RocksDBUserProfileStore::new(path.to_str().unwrap(), Duration::from_secs(60), 3);
+        let user_id = 144_u128;
+        for item_id in [1, 2, 3, 2, 4] {
+            undertest.add_user_item(&user_id, item_id).unwrap();
+        }
+        assert_eq!(vec![3, 2, 4], undertest.get_user_items(&user_id).unwrap());
+        assert!(undertest.delete_user_profile(&user_id).unwrap());
+        assert!(undertest.get_user_items(&user_id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_keep_the_items_of_concurrent_requests() {
+        let path = std::env::temp_dir().join(format!("serenade_concurrent_user_profiles_{}", std::process::id()));
+        let undertest = std::sync::Arc::new(RocksDBUserProfileStore::new(
+            path.to_str().unwrap(),
+            Duration::from_secs(60),
+            1000,
+        ));
+        let user_id = 144_u128;
+        let requests = (0..4_u64)
+            .map(|request| {
+                let store = undertest.clone();
+                std::thread::spawn(move || {
+                    for item_id in 0..50 {
+                        store.add_user_item(&user_id, request * 100 + item_id).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for request in requests {
+            request.join().unwrap();
+        }
+        assert_eq!(200, undertest.get_user_items(&user_id).unwrap().len());
+    }
+}
@@ -0,0 +1,83 @@
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+
+use crate::io::{ItemId, Time, TrainingSessionId};
+
+const SECONDS_PER_DAY: usize = 24 * 60 * 60;
+
+pub type Interaction = (TrainingSessionId, ItemId, Time);
+
+/// Splits the interactions by time: the sessions that end in the last `qty_days` become the test
+/// sessions, all earlier sessions the training sessions. Sessions are never split. Test interactions
+/// with items that are not in the training sessions are removed, and afterwards the test sessions
+/// with fewer than two interactions, because they cannot be evaluated.
+pub fn split_last_days(interactions: &[Interaction], qty_days: usize) -> (Vec<Interaction>, Vec<Interaction>) {
+    let mut session_to_max_time: HashMap<TrainingSessionId, Time> = HashMap::new();
+    for (session_id, _item_id, time) in interactions.iter() {
+        let max_time = session_to_max_time.entry(*session_id).or_insert(*time);
+        *max_time = (*max_time).max(*time);
+    }
+    let last_time = session_to_max_time.values().copied().max().unwrap_or_default();
+    let test_start = last_time.saturating_sub(qty_days * SECONDS_PER_DAY);
+
+    let (test, training): (Vec<Interaction>, Vec<Interaction>) = interactions
+        .iter()
+        .partition(|(session_id, _item_id, _time)| session_to_max_time[session_id] > test_start);
+
+    let training_items: HashSet<ItemId> = training.iter().map(|(_session_id, item_id, _time)| *item_id).collect();
+    let test = test
+        .into_iter()
+        .filter(|(_session_id, item_id, _time)| training_items.contains(item_id))
+        .collect_vec();
+    let test_session_lengths = test.iter().map(|(session_id, _item_id, _time)| *session_id).counts();
+    let test = test
+        .into_iter()
+        .filter(|(session_id, _item_id, _time)| test_session_lengths[session_id] >= 2)
+        .collect();
+
+    (sorted(training), sorted(test))
+}
+
+/// Interactions ordered by session and by time within a session.
+fn sorted(mut interactions: Vec<Interaction>) -> Vec<Interaction> {
+    interactions.sort_by_key(|(session_id, _item_id, time)| (*session_id, *time));
+    interactions
+}
+
+#[cfg(test)]
+mod split_test {
+    use super::*;
+
+    const DAY: usize = SECONDS_PER_DAY;
+
+    #[test]
+    fn should_split_whole_sessions_by_their_last_interaction() {
+        let interactions = vec![
+            (1, 10, 0),
+            (1, 11, DAY),
+            // ends in the last day, so the whole session is a test session
+            (2, 10, DAY / 2),
+            (2, 11, 2 * DAY),
+            (3, 12, 0),
+            (3, 10, 1),
+        ];
+        let (training, test) = split_last_days(&interactions, 1);
+        assert_eq!(vec![(1, 10, 0), (1, 11, DAY), (3, 12, 0), (3, 10, 1)], training);
+        assert_eq!(vec![(2, 10, DAY / 2), (2, 11, 2 * DAY)], test);
+    }
+
+    #[test]
+    fn should_remove_unseen_items_and_short_test_sessions() {
+        let interactions = vec![
+            (1, 10, 0),
+            (1, 11, 1),
+            (2, 10, 2 * DAY),
+            (2, 99, 2 * DAY + 1),
+            (2, 11, 2 * DAY + 2),
+            (3, 10, 2 * DAY),
+            (3, 98, 2 * DAY + 1),
+        ];
+        let (_training, test) = split_last_days(&interactions, 1);
+        assert_eq!(vec![(2, 10, 2 * DAY), (2, 11, 2 * DAY + 2)], test);
+    }
+}
@@ -36,11 +36,12 @@ impl Stopwatch {
         self.prediction_durations.push(tuple);
     }
 
-    pub fn get_n(&mut self) -> usize {
+    pub fn get_n(&self) -> usize {
         self.prediction_durations.len()
     }
 
-    pub fn get_percentile_in_micros(&mut self, q: f64) -> f64 {
+    /// The `q`-th percentile of the measured durations, `q` is in `[0, 100]`.
+    pub fn get_percentile_in_micros(&self, q: f64) -> f64 {
         let t_digest = TDigest::new_with_size(100);
         let durations = self
             .prediction_durations
@@ -48,10 +49,23 @@ impl Stopwatch {
             .map(|tuple| tuple.1)
             .collect();
         let sorted_digest = t_digest.merge_unsorted(durations);
-        sorted_digest.estimate_quantile(q)
+        sorted_digest.estimate_quantile(q / 100.0)
     }
 
-    pub fn get_raw_durations(&mut self) -> Vec<PositionDurationMicros> {
+    /// A stopwatch with only the durations of the predictions at the matching positions in the session.
+    pub fn filter_positions<P: Fn(u32) -> bool>(&self, predicate: P) -> Stopwatch {
+        Stopwatch {
+            start_time: self.start_time,
+            prediction_durations: self
+                .prediction_durations
+                .iter()
+                .filter(|(position, _)| predicate(*position))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn get_raw_durations(&self) -> Vec<PositionDurationMicros> {
         self.prediction_durations.clone()
     }
 }
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::vmisknn::similar_items::co_occurrence_similarity;
+use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
+use crate::vmisknn::vmis_index::VMISIndex;
+use crate::vmisknn::ItemScore;
+
+// Re-ranking picks from this many times the requested amount of recommendations.
+const CANDIDATES_PER_RECOMMENDATION: usize = 3;
+
+/// How similar two recommended items are, for the diversity re-ranking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiversitySimilarity {
+    /// Items with the same product attributes are identical, other items are unrelated.
+    Attributes,
+    /// The cosine similarity of the indexed sessions of both items.
+    CoOccurrence,
+}
+
+impl FromStr for DiversitySimilarity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "attributes" => Ok(DiversitySimilarity::Attributes),
+            "co_occurrence" => Ok(DiversitySimilarity::CoOccurrence),
+            _ => Err(format!("Unknown diversity similarity: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for DiversitySimilarity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DiversitySimilarity::Attributes => "attributes",
+            DiversitySimilarity::CoOccurrence => "co_occurrence",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Re-ranks recommendations with maximal marginal relevance, trading relevance for items that
+/// differ from the ones already picked, to avoid lists full of variants of one product.
+pub struct Diversifier {
+    similarity: DiversitySimilarity,
+    lambda: f64,
+}
+
+impl Diversifier {
+    /// # Arguments
+    ///
+    /// * `lambda` - the default weight of relevance versus diversity in `[0, 1]`. `1` keeps the
+    ///   ranking by score, `0` only optimizes for diversity.
+    pub fn new(similarity: DiversitySimilarity, lambda: f64) -> Self {
+        Diversifier {
+            similarity,
+            lambda: lambda.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn similarity(&self) -> DiversitySimilarity {
+        self.similarity
+    }
+
+    pub fn lambda(&self) -> f64 {
+        self.lambda
+    }
+
+    /// The amount of scored candidates to request for `how_many` re-ranked recommendations.
+    pub fn qty_candidates(how_many: usize, lambda: f64) -> usize {
+        if lambda < 1.0 {
+            how_many * CANDIDATES_PER_RECOMMENDATION
+        } else {
+            how_many
+        }
+    }
+
+    /// Picks `how_many` items from the candidates ordered by descending score. Every next item
+    /// maximizes `lambda * relevance - (1 - lambda) * max similarity to the picked items`, the
+    /// relevance is the min-max normalized score. The returned items keep their original score.
+    pub fn rerank(
+        &self,
+        index: &VMISIndex,
+        candidates: Vec<ItemScore>,
+        lambda: f64,
+        how_many: usize,
+    ) -> Vec<ItemScore> {
+        let lambda = lambda.clamp(0.0, 1.0);
+        if lambda >= 1.0 || candidates.len() <= 1 {
+            return candidates.into_iter().take(how_many).collect();
+        }
+        let max_score = candidates.iter().map(|scored| scored.score).fold(f64::MIN, f64::max);
+        let min_score = candidates.iter().map(|scored| scored.score).fold(f64::MAX, f64::min);
+        let score_range = max_score - min_score;
+
+        let mut remaining: Vec<(ItemScore, f64)> = candidates
+            .into_iter()
+            .map(|scored| {
+                let relevance = if score_range > 0.0 {
+                    (scored.score - min_score) / score_range
+                } else {
+                    1.0
+                };
+                (scored, relevance)
+            })
+            .collect();
+        // Per remaining candidate the highest similarity to any picked item so far.
+        let mut max_similarities = vec![0.0_f64; remaining.len()];
+        let mut reranked: Vec<ItemScore> = Vec::with_capacity(how_many);
+
+        while reranked.len() < how_many && !remaining.is_empty() {
+            let mut best_position = 0;
+            let mut best_value = f64::MIN;
+            for (position, (_scored, relevance)) in remaining.iter().enumerate() {
+                let value = lambda * relevance - (1.0 - lambda) * max_similarities[position];
+                // Strictly greater keeps the higher scored candidate on ties.
+                if value > best_value {
+                    best_value = value;
+                    best_position = position;
+                }
+            }
+            let (picked, _relevance) = remaining.remove(best_position);
+            max_similarities.remove(best_position);
+            for (position, (scored, _relevance)) in remaining.iter().enumerate() {
+                let similarity = self.item_similarity(index, &picked.id, &scored.id);
+                if similarity > max_similarities[position] {
+                    max_similarities[position] = similarity;
+                }
+            }
+            reranked.push(picked);
+        }
+        reranked
+    }
+
+    fn item_similarity(&self, index: &VMISIndex, item_a: &u64, item_b: &u64) -> f64 {
+        match self.similarity {
+            DiversitySimilarity::Attributes => {
+                match (index.find_attributes(item_a), index.find_attributes(item_b)) {
+                    (Some(attribs_a), Some(attribs_b))
+                        if attribs_a.is_adult == attribs_b.is_adult
+                            && attribs_a.is_for_sale == attribs_b.is_for_sale =>
+                    {
+                        1.0
+                    }
+                    _ => 0.0,
+                }
+            }
+            DiversitySimilarity::CoOccurrence => co_occurrence_similarity(index, item_a, item_b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod diversity_test {
+    use super::*;
+    use crate::vmisknn::test_fixtures::ids;
+
+    fn test_index() -> VMISIndex {
+        // 1 and 2 always co-occur, 3 never co-occurs with them.
+        let historical_sessions = vec![vec![1, 2], vec![1, 2], vec![3, 4]];
+        VMISIndex::new_from_sessions(historical_sessions, vec![10, 20, 30], 500)
+    }
+
+    fn candidates() -> Vec<ItemScore> {
+        vec![ItemScore::new(1, 1.0), ItemScore::new(2, 0.9), ItemScore::new(3, 0.5)]
+    }
+
+    #[test]
+    fn should_keep_the_ranking_without_diversification() {
+        let index = test_index();
+        let undertest = Diversifier::new(DiversitySimilarity::CoOccurrence, 1.0);
+        assert_eq!(vec![1, 2], ids(&undertest.rerank(&index, candidates(), 1.0, 2)));
+    }
+
+    #[test]
+    fn should_demote_items_similar_to_picked_items() {
+        let index = test_index();
+        let undertest = Diversifier::new(DiversitySimilarity::CoOccurrence, 0.5);
+        // after 1, item 2 scores 0.5 * 0.8 - 0.5 * 1.0 and item 3 scores 0.5 * 0.0 - 0.5 * 0.0
+        assert_eq!(vec![1, 3, 2], ids(&undertest.rerank(&index, candidates(), 0.5, 3)));
+    }
+
+    #[test]
+    fn should_request_more_candidates_when_diversifying() {
+        assert_eq!(21, Diversifier::qty_candidates(21, 1.0));
+        assert_eq!(63, Diversifier::qty_candidates(21, 0.7));
+    }
+
+    #[test]
+    fn should_parse_similarity_names() {
+        for name in ["attributes", "co_occurrence"] {
+            let similarity: DiversitySimilarity = name.parse().unwrap();
+            assert_eq!(name, similarity.to_string());
+        }
+        assert!("jaccard".parse::<DiversitySimilarity>().is_err());
+    }
+}
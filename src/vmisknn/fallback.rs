@@ -0,0 +1,168 @@
+use std::fmt;
+use std::str::FromStr;
+
+use hashbrown::HashMap;
+
+use crate::vmisknn::passes_business_rules;
+use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
+use crate::vmisknn::vmis_index::VMISIndex;
+
+// Amount of ranked items kept per strategy, filtering during a request needs some slack.
+const MAX_FALLBACK_CANDIDATES: usize = 1000;
+
+/// The recommendations to use when VMIS-kNN cannot recommend anything for a session,
+/// for example because the most recent item is not part of the training data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallbackStrategy {
+    /// Return no recommendations.
+    None,
+    /// The items that occur in the most training sessions.
+    Popular,
+    /// The items that occur in the most training sessions within the most recent time window.
+    Trending,
+}
+
+impl FromStr for FallbackStrategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(FallbackStrategy::None),
+            "popular" => Ok(FallbackStrategy::Popular),
+            "trending" => Ok(FallbackStrategy::Trending),
+            _ => Err(format!("Unknown fallback strategy: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for FallbackStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FallbackStrategy::None => "none",
+            FallbackStrategy::Popular => "popular",
+            FallbackStrategy::Trending => "trending",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub struct FallbackRecommender {
+    strategy: FallbackStrategy,
+    ranked_items: Vec<u64>,
+}
+
+impl FallbackRecommender {
+    /// Ranks the items of the training sessions in the index once, at load time.
+    ///
+    /// # Arguments
+    ///
+    /// * `trending_window_in_secs` - only for `Trending`, sessions that ended longer than this before the
+    ///   most recent training session are ignored.
+    pub fn new(index: &VMISIndex, strategy: FallbackStrategy, trending_window_in_secs: u32) -> Self {
+        let ranked_items = match strategy {
+            FallbackStrategy::None => Vec::new(),
+            FallbackStrategy::Popular => rank_items(index, 0),
+            FallbackStrategy::Trending => {
+                let max_time_stamp = index
+                    .session_to_max_time_stamp
+                    .iter()
+                    .max()
+                    .cloned()
+                    .unwrap_or(0);
+                rank_items(index, max_time_stamp.saturating_sub(trending_window_in_secs))
+            }
+        };
+        FallbackRecommender {
+            strategy,
+            ranked_items,
+        }
+    }
+
+    pub fn strategy(&self) -> FallbackStrategy {
+        self.strategy
+    }
+
+    /// Returns up to `how_many` fallback recommendations for the evolving session, excluding
+    /// the items in the session itself.
+    pub fn recommend(
+        &self,
+        index: &VMISIndex,
+        evolving_session: &[u64],
+        how_many: usize,
+        enable_business_logic: bool,
+    ) -> Vec<u64> {
+        let current_item_attribs = evolving_session
+            .last()
+            .and_then(|item_id| index.find_attributes(item_id));
+        self.ranked_items
+            .iter()
+            .filter(|item_id| !evolving_session.contains(item_id))
+            .filter(|item_id| {
+                !enable_business_logic
+                    || passes_business_rules(current_item_attribs, index.find_attributes(item_id))
+            })
+            .take(how_many)
+            .cloned()
+            .collect()
+    }
+}
+
+// Ranks items by the amount of training sessions that contain them and ended at or after `min_time_stamp`.
+// Ties are broken by item id to keep the ranking reproducible.
+fn rank_items(index: &VMISIndex, min_time_stamp: u32) -> Vec<u64> {
+    let mut item_counts: HashMap<u64, usize> = HashMap::new();
+    for (session_id, session_items) in index.session_to_items_sorted.iter().enumerate() {
+        if index.session_to_max_time_stamp[session_id] >= min_time_stamp {
+            for item_id in session_items.iter() {
+                *item_counts.entry(*item_id).or_insert(0) += 1;
+            }
+        }
+    }
+    let mut ranked: Vec<(u64, usize)> = item_counts.into_iter().collect();
+    ranked.sort_unstable_by(|(item_a, count_a), (item_b, count_b)| {
+        count_b.cmp(count_a).then(item_a.cmp(item_b))
+    });
+    ranked.truncate(MAX_FALLBACK_CANDIDATES);
+    ranked.into_iter().map(|(item_id, _count)| item_id).collect()
+}
+
+#[cfg(test)]
+mod fallback_test {
+    use super::*;
+    use crate::vmisknn::test_fixtures::five_session_index;
+
+    fn test_index() -> VMISIndex {
+        five_session_index(vec![10, 10, 10, 100, 100])
+    }
+
+    #[test]
+    fn should_recommend_popular_items() {
+        let index = test_index();
+        let undertest = FallbackRecommender::new(&index, FallbackStrategy::Popular, 0);
+        assert_eq!(vec![1, 4, 2], undertest.recommend(&index, &[999], 3, false));
+        assert_eq!(vec![4, 2], undertest.recommend(&index, &[1], 2, false));
+    }
+
+    #[test]
+    fn should_recommend_trending_items() {
+        let index = test_index();
+        let undertest = FallbackRecommender::new(&index, FallbackStrategy::Trending, 50);
+        assert_eq!(vec![4, 5], undertest.recommend(&index, &[999], 3, false));
+    }
+
+    #[test]
+    fn should_recommend_nothing_without_strategy() {
+        let index = test_index();
+        let undertest = FallbackRecommender::new(&index, FallbackStrategy::None, 0);
+        assert!(undertest.recommend(&index, &[999], 3, false).is_empty());
+    }
+
+    #[test]
+    fn should_parse_strategy_names() {
+        for name in ["none", "popular", "trending"] {
+            let strategy: FallbackStrategy = name.parse().unwrap();
+            assert_eq!(name, strategy.to_string());
+        }
+        assert!("unknown".parse::<FallbackStrategy>().is_err());
+    }
+}
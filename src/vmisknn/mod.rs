@@ -1,16 +1,23 @@
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
+use std::fmt;
 
 use hashbrown::hash_map::Entry;
 use hashbrown::HashMap;
 
+use crate::recommender::{recommended_or_logged, RecommendOptions, Recommender};
 use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
-use crate::vmisknn::vmis_index::ProductAttributes;
+use crate::vmisknn::vmis_index::{ProductAttributes, VMISIndex};
 
+pub mod diversity;
+pub mod fallback;
+pub mod similar_items;
 pub mod vsknn_index;
 pub mod similarity_hashed;
 pub mod similarity_indexed;
 pub mod vmis_index;
+#[cfg(test)]
+pub(crate) mod test_fixtures;
 
 #[derive(PartialEq, Debug)]
 pub struct SessionScore {
@@ -28,10 +35,11 @@ impl Eq for SessionScore {}
 
 impl Ord for SessionScore {
     fn cmp(&self, other: &Self) -> Ordering {
+        // reverse order by score, equal scores are ordered by session id to keep neighborhoods reproducible
         match self.score.partial_cmp(&other.score) {
             Some(Ordering::Less) => Ordering::Greater,
             Some(Ordering::Greater) => Ordering::Less,
-            _ => Ordering::Equal,
+            _ => self.id.cmp(&other.id),
         }
     }
 }
@@ -49,7 +57,7 @@ pub struct ItemScore {
 }
 
 impl ItemScore {
-    fn new(id: u64, score: f64) -> Self {
+    pub fn new(id: u64, score: f64) -> Self {
         ItemScore { id, score }
     }
 }
@@ -58,11 +66,12 @@ impl Eq for ItemScore {}
 
 impl Ord for ItemScore {
     fn cmp(&self, other: &Self) -> Ordering {
-        // reverse order by score
+        // reverse order by score, equal scores are ordered by item id so that recommendations do not
+        // depend on the iteration order of hash maps, which differs between runs and replicas
         match self.score.partial_cmp(&other.score) {
             Some(Ordering::Less) => Ordering::Greater,
             Some(Ordering::Greater) => Ordering::Less,
-            _ => Ordering::Equal,
+            _ => self.id.cmp(&other.id),
         }
     }
 }
@@ -107,7 +116,7 @@ impl PartialEq for SessionTime {
 }
 
 
-fn linear_score(pos: usize) -> f64 {
+pub(crate) fn linear_score(pos: usize) -> f64 {
     if pos < 100 {
         1.0 - (0.1 * pos as f64)
     } else {
@@ -115,6 +124,57 @@ fn linear_score(pos: usize) -> f64 {
     }
 }
 
+pub(crate) fn passes_business_rules(
+    current_item_attribs: Option<&ProductAttributes>,
+    reco_item_attribs: Option<&ProductAttributes>,
+) -> bool {
+    if reco_item_attribs.is_none() {
+        return false;
+    }
+    let reco_attribs = reco_item_attribs.unwrap();
+    if reco_attribs.is_for_sale {
+        if reco_attribs.is_adult {
+            if let Some(current_attribs) = current_item_attribs {
+                return current_attribs.is_adult;
+            } else {
+                return false;
+            }
+        } else {
+            return true;
+        }
+    }
+    false
+}
+
+/// Reasons why `predict` cannot score an evolving session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PredictError {
+    /// The evolving session holds no items, so there are no neighbors to score items with.
+    EmptySession,
+}
+
+impl fmt::Display for PredictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PredictError::EmptySession => write!(f, "cannot predict for an empty evolving session"),
+        }
+    }
+}
+
+impl std::error::Error for PredictError {}
+
+/// Collapses repeated consecutive interactions with the same item into one, e.g. refreshes of a
+/// product page, while keeping an item that is revisited after other items.
+pub fn dedup_consecutive_items(evolving_session: &[u64]) -> Vec<u64> {
+    let mut deduplicated = evolving_session.to_vec();
+    deduplicated.dedup();
+    deduplicated
+}
+
+/// Scores the items of the `k` closest neighbor sessions of the evolving session.
+///
+/// Neighbor sessions that share no item with the evolving session do not contribute. Returns at
+/// most `how_many` items, an empty evolving session is an error.
 pub fn predict<I: SimilarityComputationNew + Send + Sync>(
     index: &I,
     evolving_session: &[u64],
@@ -122,22 +182,40 @@ pub fn predict<I: SimilarityComputationNew + Send + Sync>(
     m: usize,
     how_many: usize,
     enable_business_logic: bool,
-) -> BinaryHeap<ItemScore> {
+    dedup_consecutive: bool,
+) -> Result<BinaryHeap<ItemScore>, PredictError> {
+    let deduplicated;
+    let evolving_session = if dedup_consecutive {
+        deduplicated = dedup_consecutive_items(evolving_session);
+        &deduplicated[..]
+    } else {
+        evolving_session
+    };
+    let most_recent_item = match evolving_session.last() {
+        Some(item_id) => *item_id,
+        None => return Err(PredictError::EmptySession),
+    };
+    if how_many == 0 {
+        return Ok(BinaryHeap::new());
+    }
+
     let neighbors = index.find_neighbors(evolving_session, k, m);
 
     let mut item_scores: HashMap<u64, f64> = HashMap::with_capacity(1000);
 
-    for scored_session in neighbors.into_iter() {
+    // Sum the neighbor contributions in a fixed order, floating point sums depend on the order.
+    for scored_session in neighbors.into_sorted_vec().into_iter() {
         let training_item_ids: &[u64] = index.items_for_session(&scored_session.id);
 
-        let (first_match_index, _) = evolving_session
+        let first_match_index = evolving_session
             .iter()
             .rev()
-            .enumerate()
-            .find(|(_, item_id)| training_item_ids.contains(*item_id))
-            .unwrap();
-
-        let first_match_pos = first_match_index + 1;
+            .position(|item_id| training_item_ids.contains(item_id));
+        let first_match_pos = match first_match_index {
+            Some(first_match_index) => first_match_index + 1,
+            // Only indexes that return unrelated sessions end up here, such a session says nothing.
+            None => continue,
+        };
 
         let session_weight = linear_score(first_match_pos);
 
@@ -154,33 +232,10 @@ pub fn predict<I: SimilarityComputationNew + Send + Sync>(
     }
 
     // Remove most recent item if it has been scored as well
-    let most_recent_item = *evolving_session.last().unwrap();
     if let Entry::Occupied(entry) = item_scores.entry(most_recent_item) {
         entry.remove_entry();
     }
 
-    fn passes_business_rules(
-        current_item_attribs: Option<&ProductAttributes>,
-        reco_item_attribs: Option<&ProductAttributes>,
-    ) -> bool {
-        if reco_item_attribs.is_none() {
-            return false;
-        }
-        let reco_attribs = reco_item_attribs.unwrap();
-        if reco_attribs.is_for_sale {
-            if reco_attribs.is_adult {
-                if let Some(current_attribs) = current_item_attribs {
-                    return current_attribs.is_adult;
-                } else {
-                    return false;
-                }
-            } else {
-                return true;
-            }
-        }
-        false
-    }
-
     // Return the proper amount of recommendations and filter them using business rules.
     let mut top_items: BinaryHeap<ItemScore> = BinaryHeap::with_capacity(how_many);
     let current_item_attribs: Option<&ProductAttributes> = index.find_attributes(&most_recent_item);
@@ -198,7 +253,8 @@ pub fn predict<I: SimilarityComputationNew + Send + Sync>(
             }
         } else {
             let mut bottom = top_items.peek_mut().unwrap();
-            if scored_item.score > bottom.score {
+            // ordering is reverse, thus a smaller item is a better recommendation than the bottom.
+            if scored_item < *bottom {
                 if enable_business_logic {
                     let reco_item_attribs = index.find_attributes(&reco_item_id);
                     if passes_business_rules(current_item_attribs, reco_item_attribs) {
@@ -211,7 +267,30 @@ pub fn predict<I: SimilarityComputationNew + Send + Sync>(
         }
     }
 
-    top_items
+    Ok(top_items)
+}
+
+impl Recommender for VMISIndex {
+    fn recommend(&self, evolving_session: &[u64], options: &RecommendOptions) -> Vec<ItemScore> {
+        recommended_or_logged(self.try_recommend(evolving_session, options))
+    }
+
+    fn try_recommend(
+        &self,
+        evolving_session: &[u64],
+        options: &RecommendOptions,
+    ) -> Result<Vec<ItemScore>, PredictError> {
+        predict(
+            self,
+            evolving_session,
+            options.neighborhood_size_k,
+            options.m_most_recent_sessions,
+            options.num_items_to_recommend,
+            options.enable_business_logic,
+            options.dedup_consecutive_items,
+        )
+        .map(BinaryHeap::into_sorted_vec)
+    }
 }
 
 #[cfg(test)]
@@ -220,10 +299,12 @@ mod vmisknn_test {
 
     use crate::dataframeutils::TrainingDataStats;
     use crate::vmisknn::vmis_index::prepare_hashmap;
-    use crate::vmisknn::vmis_index::VMISIndex;
+    use crate::vmisknn::vmis_index::{NeighborSimilarity, VMISIndex};
 
     use super::*;
     use dary_heap::OctonaryHeap;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
 
     #[test]
     fn should_train_and_predict() {
@@ -287,14 +368,17 @@ mod vmisknn_test {
             item_to_top_sessions_ordered: item_to_top_sessions_ordered,
             session_to_max_time_stamp: historical_sessions_max_time_stamp,
             item_to_idf_score: item_to_idf_score,
+            session_to_qty_unique_items: crate::vmisknn::vmis_index::count_unique_items(&historical_sessions_train),
             session_to_items_sorted: historical_sessions_train,
             training_data_stats: training_data_stats,
             item_to_product_attributes: item_to_product_attributes,
+            neighbor_similarity: NeighborSimilarity::DotProduct,
         };
 
         let session_items = vec![920005];
 
-        let recommendations = predict(&vmis_index, &session_items, k, m, how_many, enable_business_logic);
+        let recommendations =
+            predict(&vmis_index, &session_items, k, m, how_many, enable_business_logic, false).unwrap();
 
         // we expect the four other item_ids to be recommended
         assert_eq!(4, recommendations.len());
@@ -410,6 +494,139 @@ mod vmisknn_test {
         assert_eq!(123, heap_timestamps.pop().unwrap().session_id);
     }
 
+    fn tied_index() -> VMISIndex {
+        // every session holds item 1 and one other item, all at the same time.
+        let historical_sessions: Vec<Vec<u64>> = (2..=21).map(|item_id| vec![1, item_id]).collect();
+        let timestamps = vec![100; historical_sessions.len()];
+        VMISIndex::new_from_sessions(historical_sessions, timestamps, 500)
+    }
 
+    fn predicted_ids(index: &VMISIndex, k: usize, how_many: usize) -> Vec<u64> {
+        predict(index, &[1], k, 500, how_many, false, false)
+            .unwrap()
+            .into_sorted_vec()
+            .iter()
+            .map(|scored| scored.id)
+            .collect()
+    }
+
+    #[test]
+    fn should_break_ties_by_item_id() {
+        let equal = ItemScore::new(7, 1.0);
+        assert!(equal < ItemScore::new(8, 1.0));
+        assert!(SessionScore::new(7, 1.0) < SessionScore::new(8, 1.0));
+
+        let expected = predicted_ids(&tied_index(), 500, 5);
+        assert_eq!(vec![2, 3, 4, 5, 6], expected);
+        // every index has differently seeded hash maps
+        for _ in 0..10 {
+            assert_eq!(expected, predicted_ids(&tied_index(), 500, 5));
+        }
+    }
+
+    #[test]
+    fn should_break_ties_between_neighbors_by_session_id() {
+        let expected = predicted_ids(&tied_index(), 3, 10);
+        assert_eq!(vec![2, 3, 4], expected);
+        for _ in 0..10 {
+            assert_eq!(expected, predicted_ids(&tied_index(), 3, 10));
+        }
+    }
+
+    // Returns every training session as a neighbor, also the ones without a shared item.
+    struct UnrelatedNeighbors(VMISIndex);
+
+    impl SimilarityComputationNew for UnrelatedNeighbors {
+        fn items_for_session(&self, session_idx: &u32) -> &[u64] {
+            self.0.items_for_session(session_idx)
+        }
+
+        fn idf(&self, item_id: &u64) -> f64 {
+            self.0.idf(item_id)
+        }
+
+        fn find_neighbors(&self, _evolving_session: &[u64], _k: usize, _m: usize) -> BinaryHeap<SessionScore> {
+            (0..self.0.session_to_items_sorted.len() as u32)
+                .map(|session_id| SessionScore::new(session_id, 1.0))
+                .collect()
+        }
+
+        fn find_attributes(&self, item_id: &u64) -> Option<&ProductAttributes> {
+            self.0.find_attributes(item_id)
+        }
+    }
+
+    fn random_index(rng: &mut StdRng) -> VMISIndex {
+        let historical_sessions: Vec<Vec<u64>> = (0..50)
+            .map(|_| {
+                let session_length = rng.gen_range(1..8);
+                (0..session_length).map(|_| rng.gen_range(0..30)).collect()
+            })
+            .collect();
+        let timestamps = (0..historical_sessions.len()).map(|_| rng.gen_range(0..10)).collect();
+        VMISIndex::new_from_sessions(historical_sessions, timestamps, 20)
+    }
+
+    #[test]
+    fn should_reject_empty_sessions() {
+        let index = tied_index();
+        for dedup_consecutive in [false, true] {
+            let result = predict(&index, &[], 500, 500, 5, false, dedup_consecutive);
+            assert_eq!(Some(PredictError::EmptySession), result.err());
+        }
+        let options = RecommendOptions {
+            neighborhood_size_k: 500,
+            m_most_recent_sessions: 500,
+            num_items_to_recommend: 5,
+            enable_business_logic: false,
+            dedup_consecutive_items: false,
+        };
+        assert!(index.recommend(&[], &options).is_empty());
+        assert_eq!(Some(PredictError::EmptySession), index.try_recommend(&[], &options).err());
+    }
 
+    #[test]
+    fn should_skip_neighbors_without_shared_items() {
+        let index = UnrelatedNeighbors(VMISIndex::new_from_sessions(
+            vec![vec![1, 2], vec![3, 4]],
+            vec![10, 20],
+            500,
+        ));
+        let recommendations = predict(&index, &[1], 500, 500, 5, false, false).unwrap().into_sorted_vec();
+        // only the first session shares item 1, the items of the second session are not scored
+        assert_eq!(vec![2], recommendations.iter().map(|scored| scored.id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn should_deduplicate_consecutive_items() {
+        assert_eq!(vec![1, 2, 1, 3], dedup_consecutive_items(&[1, 1, 2, 1, 3, 3, 3]));
+        assert!(dedup_consecutive_items(&[]).is_empty());
+    }
+
+    #[test]
+    fn should_predict_for_random_sessions() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..20 {
+            let index = random_index(&mut rng);
+            for _ in 0..50 {
+                let session_length = rng.gen_range(1..10);
+                // few distinct items, so sessions hold consecutive repeats
+                let session: Vec<u64> = (0..session_length).map(|_| rng.gen_range(0..35)).collect();
+                let how_many = rng.gen_range(0..25);
+                let k = rng.gen_range(0..60);
+
+                let recommendations = predict(&index, &session, k, 20, how_many, false, false)
+                    .unwrap()
+                    .into_sorted_vec();
+                assert!(recommendations.len() <= how_many);
+                assert!(recommendations.iter().all(|scored| scored.id != *session.last().unwrap()));
+                assert!(recommendations.windows(2).all(|pair| pair[0] < pair[1]));
+
+                let deduplicated = dedup_consecutive_items(&session);
+                let with_dedup = predict(&index, &session, k, 20, how_many, false, true).unwrap();
+                let of_deduplicated = predict(&index, &deduplicated, k, 20, how_many, false, false).unwrap();
+                assert_eq!(of_deduplicated.into_sorted_vec(), with_dedup.into_sorted_vec());
+            }
+        }
+    }
 }
@@ -0,0 +1,269 @@
+use std::fmt;
+use std::str::FromStr;
+
+use hashbrown::{HashMap, HashSet};
+use rayon::prelude::*;
+
+use crate::vmisknn::passes_business_rules;
+use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
+use crate::vmisknn::vmis_index::VMISIndex;
+use crate::vmisknn::ItemScore;
+
+// Amount of similar items kept per precomputed item, requests that filter out too many of them are
+// computed on the fly.
+const MAX_SIMILAR_CANDIDATES: usize = 100;
+
+/// How the co-occurrence of two items in the indexed training sessions becomes a similarity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemSimilarity {
+    /// Co-occurring sessions divided by the geometric mean of the sessions per item.
+    Cosine,
+    /// Share of the sessions of the item in which the other item occurs, weighted by the idf
+    /// score of the other item to demote generally popular items.
+    IdfWeighted,
+}
+
+impl FromStr for ItemSimilarity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "cosine" => Ok(ItemSimilarity::Cosine),
+            "idf" => Ok(ItemSimilarity::IdfWeighted),
+            _ => Err(format!("Unknown item similarity: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for ItemSimilarity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ItemSimilarity::Cosine => "cosine",
+            ItemSimilarity::IdfWeighted => "idf",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Item-to-item similarities derived from the `m` most recent sessions per item in a `VMISIndex`,
+/// for recommendations that do not depend on the visitor session.
+pub struct SimilarItems {
+    similarity: ItemSimilarity,
+    precomputed: HashMap<u64, Vec<ItemScore>>,
+}
+
+impl SimilarItems {
+    /// # Arguments
+    ///
+    /// * `qty_precomputed_items` - the similar items of this many items, the ones occurring in the
+    ///   most indexed sessions, are computed at load time. Other items are computed per request.
+    pub fn new(index: &VMISIndex, similarity: ItemSimilarity, qty_precomputed_items: usize) -> Self {
+        let mut items_by_qty_sessions: Vec<(u64, usize)> = index
+            .item_to_top_sessions_ordered
+            .iter()
+            .map(|(item_id, session_ids)| (*item_id, session_ids.len()))
+            .collect();
+        items_by_qty_sessions.sort_unstable_by(|(item_a, qty_a), (item_b, qty_b)| {
+            qty_b.cmp(qty_a).then(item_a.cmp(item_b))
+        });
+        items_by_qty_sessions.truncate(qty_precomputed_items);
+
+        let precomputed = items_by_qty_sessions
+            .par_iter()
+            .map(|(item_id, _qty_sessions)| {
+                let similar_items = compute_similar_items(index, similarity, item_id, MAX_SIMILAR_CANDIDATES);
+                (*item_id, similar_items)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect();
+
+        SimilarItems {
+            similarity,
+            precomputed,
+        }
+    }
+
+    pub fn similarity(&self) -> ItemSimilarity {
+        self.similarity
+    }
+
+    pub fn qty_precomputed_items(&self) -> usize {
+        self.precomputed.len()
+    }
+
+    /// Returns up to `how_many` items most similar to `item_id`, the most similar first.
+    /// Unknown items have no similar items.
+    pub fn similar_items(
+        &self,
+        index: &VMISIndex,
+        item_id: &u64,
+        how_many: usize,
+        enable_business_logic: bool,
+    ) -> Vec<ItemScore> {
+        let current_item_attribs = index.find_attributes(item_id);
+        let passes_filters = |scored: &ItemScore| {
+            !enable_business_logic || passes_business_rules(current_item_attribs, index.find_attributes(&scored.id))
+        };
+        if let Some(similar_items) = self.precomputed.get(item_id) {
+            let filtered: Vec<ItemScore> = similar_items
+                .iter()
+                .filter(|scored| passes_filters(scored))
+                .take(how_many)
+                .map(|scored| ItemScore::new(scored.id, scored.score))
+                .collect();
+            // A truncated list may lack items that pass the filters, then the items are computed on the fly.
+            if filtered.len() == how_many || similar_items.len() < MAX_SIMILAR_CANDIDATES {
+                return filtered;
+            }
+        }
+        compute_similar_items(index, self.similarity, item_id, usize::MAX)
+            .into_iter()
+            .filter(|scored| passes_filters(scored))
+            .take(how_many)
+            .collect()
+    }
+}
+
+/// The cosine similarity of the indexed sessions of two items, `0` when either item is unknown.
+pub(crate) fn co_occurrence_similarity(index: &VMISIndex, item_a: &u64, item_b: &u64) -> f64 {
+    let (sessions_a, sessions_b) = match (
+        index.item_to_top_sessions_ordered.get(item_a),
+        index.item_to_top_sessions_ordered.get(item_b),
+    ) {
+        (Some(sessions_a), Some(sessions_b)) => (sessions_a, sessions_b),
+        _ => return 0.0,
+    };
+    let (smaller, larger) = if sessions_a.len() <= sessions_b.len() {
+        (sessions_a, sessions_b)
+    } else {
+        (sessions_b, sessions_a)
+    };
+    let smaller: HashSet<&u32> = smaller.iter().collect();
+    let qty_co_occurrences = larger.iter().filter(|session_id| smaller.contains(session_id)).count();
+    qty_co_occurrences as f64 / ((sessions_a.len() * sessions_b.len()) as f64).sqrt()
+}
+
+// Scores all items that co-occur with `item_id` in its indexed sessions, ties are broken by item id.
+fn compute_similar_items(
+    index: &VMISIndex,
+    similarity: ItemSimilarity,
+    item_id: &u64,
+    how_many: usize,
+) -> Vec<ItemScore> {
+    let session_ids = match index.item_to_top_sessions_ordered.get(item_id) {
+        Some(session_ids) => session_ids,
+        None => return Vec::new(),
+    };
+    let mut co_occurrences: HashMap<u64, usize> = HashMap::new();
+    for session_id in session_ids.iter() {
+        for other_item_id in index.items_for_session(session_id).iter() {
+            if other_item_id != item_id {
+                *co_occurrences.entry(*other_item_id).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let qty_sessions = session_ids.len() as f64;
+    let mut similar_items: Vec<ItemScore> = co_occurrences
+        .into_iter()
+        .map(|(other_item_id, qty_co_occurrences)| {
+            let score = match similarity {
+                ItemSimilarity::Cosine => {
+                    let qty_other_sessions = index
+                        .item_to_top_sessions_ordered
+                        .get(&other_item_id)
+                        .map(|other_session_ids| other_session_ids.len())
+                        .unwrap_or(1) as f64;
+                    qty_co_occurrences as f64 / (qty_sessions * qty_other_sessions).sqrt()
+                }
+                ItemSimilarity::IdfWeighted => {
+                    let other_idf = index.idf(&other_item_id);
+                    let item_weight = if other_idf > 0.0 { other_idf } else { 1.0 };
+                    qty_co_occurrences as f64 / qty_sessions * item_weight
+                }
+            };
+            ItemScore::new(other_item_id, score)
+        })
+        .collect();
+    similar_items.sort_unstable_by(|a, b| {
+        b.score.partial_cmp(&a.score).unwrap().then(a.id.cmp(&b.id))
+    });
+    similar_items.truncate(how_many);
+    similar_items
+}
+
+#[cfg(test)]
+mod similar_items_test {
+    use super::*;
+    use crate::vmisknn::test_fixtures::{five_session_index, ids};
+
+    fn test_index() -> VMISIndex {
+        five_session_index(vec![10, 20, 30, 40, 50])
+    }
+
+    #[test]
+    fn should_rank_co_occurring_items_by_cosine_similarity() {
+        let index = test_index();
+        let undertest = SimilarItems::new(&index, ItemSimilarity::Cosine, 0);
+
+        let similar_items = undertest.similar_items(&index, &1, 10, false);
+
+        // 2: 2 / sqrt(3 * 2), 3: 1 / sqrt(3 * 1), 4: 1 / sqrt(3 * 3)
+        assert_eq!(vec![2, 3, 4], ids(&similar_items));
+        assert!((similar_items[0].score - 2.0 / 6_f64.sqrt()).abs() < 1e-9);
+        assert_eq!(vec![2], ids(&undertest.similar_items(&index, &1, 1, false)));
+    }
+
+    #[test]
+    fn should_return_the_same_items_when_precomputed() {
+        let index = test_index();
+        let computed = SimilarItems::new(&index, ItemSimilarity::IdfWeighted, 0);
+        let precomputed = SimilarItems::new(&index, ItemSimilarity::IdfWeighted, 2);
+
+        assert_eq!(2, precomputed.qty_precomputed_items());
+        for item_id in 1..=5 {
+            assert_eq!(
+                ids(&computed.similar_items(&index, &item_id, 10, true)),
+                ids(&precomputed.similar_items(&index, &item_id, 10, true))
+            );
+        }
+    }
+
+    #[test]
+    fn should_return_the_same_items_when_filtering_a_truncated_precomputed_list() {
+        // item 1 co-occurs once with each of the items 2 to 151, which tie on the similarity.
+        let historical_sessions: Vec<Vec<u64>> = (2..=151).map(|item_id| vec![1, item_id]).collect();
+        let timestamps = vec![10; historical_sessions.len()];
+        let mut index = VMISIndex::new_from_sessions(historical_sessions, timestamps, 500);
+        for item_id in 2..=60 {
+            index.item_to_product_attributes.get_mut(&item_id).unwrap().is_for_sale = false;
+        }
+        let computed = SimilarItems::new(&index, ItemSimilarity::Cosine, 0);
+        let precomputed = SimilarItems::new(&index, ItemSimilarity::Cosine, 1);
+
+        let expected: Vec<u64> = (61..=110).collect();
+        assert_eq!(expected, ids(&computed.similar_items(&index, &1, 50, true)));
+        assert_eq!(expected, ids(&precomputed.similar_items(&index, &1, 50, true)));
+        assert_eq!(
+            ids(&computed.similar_items(&index, &1, 20, true)),
+            ids(&precomputed.similar_items(&index, &1, 20, true))
+        );
+    }
+
+    #[test]
+    fn should_return_nothing_for_unknown_items() {
+        let index = test_index();
+        let undertest = SimilarItems::new(&index, ItemSimilarity::Cosine, 5);
+        assert!(undertest.similar_items(&index, &999, 10, false).is_empty());
+    }
+
+    #[test]
+    fn should_parse_similarity_names() {
+        for name in ["cosine", "idf"] {
+            let similarity: ItemSimilarity = name.parse().unwrap();
+            assert_eq!(name, similarity.to_string());
+        }
+        assert!("jaccard".parse::<ItemSimilarity>().is_err());
+    }
+}
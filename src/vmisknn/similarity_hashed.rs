@@ -5,6 +5,7 @@ use std::collections::BinaryHeap;
 use hashbrown::hash_map::DefaultHashBuilder as RandomState;
 use hashbrown::HashSet;
 
+use crate::vmisknn::vmis_index::ProductAttributes;
 use crate::vmisknn::SessionScore;
 
 pub trait SimilarityComputationHash {
@@ -12,6 +13,8 @@ pub trait SimilarityComputationHash {
 
     fn idf(&self, item_id: &u64) -> f64;
 
+    fn find_attributes(&self, item_id: &u64) -> Option<&ProductAttributes>;
+
     fn find_neighbors(
         &self,
         evolving_session: &[u64],
@@ -0,0 +1,19 @@
+use crate::vmisknn::vmis_index::VMISIndex;
+use crate::vmisknn::ItemScore;
+
+/// Five training sessions, one per timestamp: item 1 co-occurs with 2 twice and with 3 and 4 once,
+/// items 4 and 5 co-occur twice.
+pub(crate) fn five_session_index(timestamps: Vec<u32>) -> VMISIndex {
+    let historical_sessions = vec![
+        vec![1, 2, 3],
+        vec![1, 2],
+        vec![1, 4],
+        vec![4, 5],
+        vec![4, 5],
+    ];
+    VMISIndex::new_from_sessions(historical_sessions, timestamps, 500)
+}
+
+pub(crate) fn ids(scored_items: &[ItemScore]) -> Vec<u64> {
+    scored_items.iter().map(|scored| scored.id).collect()
+}
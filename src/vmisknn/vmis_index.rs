@@ -1,4 +1,5 @@
 use crate::dataframeutils::TrainingDataStats;
+use crate::io::{ItemId, Time, TrainingSessionId};
 use crate::vmisknn::similarity_indexed::SimilarityComputationNew;
 use crate::vmisknn::SessionScore;
 use crate::vmisknn::SessionTime;
@@ -7,6 +8,9 @@ use dary_heap::OctonaryHeap;
 use hashbrown::HashMap;
 use rayon::prelude::*;
 use serde::Deserialize;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
 use std::collections::BinaryHeap;
 use std::fs;
 use std::fs::File;
@@ -25,13 +29,78 @@ pub struct ProductAttributes {
     pub(crate) is_for_sale: bool,
 }
 
+/// How the decayed dot product of the evolving session and a neighbor session is normalized
+/// by the session lengths, so long neighbor sessions do not dominate the neighborhood.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighborSimilarity {
+    /// The unnormalized decayed dot product.
+    DotProduct,
+    /// Divided by the geometric mean of the amount of unique items in both sessions.
+    Cosine,
+    /// Divided by the amount of unique items in the union of both sessions.
+    Jaccard,
+}
+
+impl NeighborSimilarity {
+    pub const ALL: [NeighborSimilarity; 3] = [
+        NeighborSimilarity::DotProduct,
+        NeighborSimilarity::Cosine,
+        NeighborSimilarity::Jaccard,
+    ];
+
+    fn normalize(&self, score: f64, qty_shared: usize, qty_evolving: usize, qty_neighbor: usize) -> f64 {
+        match self {
+            NeighborSimilarity::DotProduct => score,
+            NeighborSimilarity::Cosine => score / ((qty_evolving * qty_neighbor) as f64).sqrt(),
+            NeighborSimilarity::Jaccard => score / (qty_evolving + qty_neighbor - qty_shared) as f64,
+        }
+    }
+}
+
+impl FromStr for NeighborSimilarity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        NeighborSimilarity::ALL
+            .iter()
+            .find(|similarity| similarity.to_string() == value)
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "Unknown neighbor similarity: {}, expected one of {}",
+                    value,
+                    NeighborSimilarity::ALL.iter().join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for NeighborSimilarity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NeighborSimilarity::DotProduct => "dot_product",
+            NeighborSimilarity::Cosine => "cosine",
+            NeighborSimilarity::Jaccard => "jaccard",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Parses comma separated neighbor similarity names, e.g. `dot_product,cosine`.
+pub fn parse_neighbor_similarities(value: &str) -> Result<Vec<NeighborSimilarity>, String> {
+    value.split(',').map(|name| name.trim().parse()).collect()
+}
+
 pub struct VMISIndex {
     pub(crate) item_to_top_sessions_ordered: HashMap<u64, Vec<u32>>,
     pub(crate) session_to_max_time_stamp: Vec<u32>,
     pub(crate) item_to_idf_score: HashMap<u64, f64>,
     pub(crate) session_to_items_sorted: Vec<Vec<u64>>,
+    // The amount of unique items per session, repeated clicks on an item count once.
+    pub(crate) session_to_qty_unique_items: Vec<u32>,
     pub(crate) training_data_stats: TrainingDataStats,
     pub(crate) item_to_product_attributes: HashMap<u64, ProductAttributes>,
+    pub(crate) neighbor_similarity: NeighborSimilarity,
 }
 
 impl VMISIndex {
@@ -76,9 +145,93 @@ impl VMISIndex {
             item_to_top_sessions_ordered,
             session_to_max_time_stamp: historical_sessions_max_time_stamp,
             item_to_idf_score,
+            session_to_qty_unique_items: count_unique_items(&historical_sessions_train),
             session_to_items_sorted: historical_sessions_train,
             training_data_stats,
             item_to_product_attributes,
+            neighbor_similarity: NeighborSimilarity::DotProduct,
+        }
+    }
+
+    /// Uses `neighbor_similarity` to score the neighbor sessions, the default is the dot product.
+    pub fn with_neighbor_similarity(mut self, neighbor_similarity: NeighborSimilarity) -> Self {
+        self.neighbor_similarity = neighbor_similarity;
+        self
+    }
+
+    pub fn neighbor_similarity(&self) -> NeighborSimilarity {
+        self.neighbor_similarity
+    }
+
+    /// The indexed training sessions as interactions, every item of a session gets the time of
+    /// the last event of that session. Used to derive other models from an index read from avro files.
+    pub fn session_interactions(&self) -> Vec<(TrainingSessionId, ItemId, Time)> {
+        self.session_to_items_sorted
+            .iter()
+            .enumerate()
+            .flat_map(|(session_id, session_items)| {
+                let time = self.session_to_max_time_stamp[session_id] as Time;
+                session_items
+                    .iter()
+                    .map(move |item_id| (session_id as TrainingSessionId, *item_id, time))
+            })
+            .collect()
+    }
+
+    /// Creates an index from in-memory training sessions, used by unit tests.
+    #[cfg(test)]
+    pub(crate) fn new_from_sessions(
+        historical_sessions: Vec<Vec<u64>>,
+        historical_sessions_max_time_stamp: Vec<u32>,
+        m_most_recent_sessions: usize,
+    ) -> Self {
+        let qty_records = historical_sessions.iter().map(|session| session.len()).sum();
+        let mut unique_items = historical_sessions.iter().flatten().collect_vec();
+        unique_items.sort_unstable();
+        unique_items.dedup();
+        let training_data_stats = TrainingDataStats {
+            descriptive_name: "unittest".to_string(),
+            qty_records,
+            qty_unique_session_ids: historical_sessions.len(),
+            qty_unique_item_ids: unique_items.len(),
+            min_time_date_time: NaiveDateTime::default(),
+            max_time_date_time: NaiveDateTime::default(),
+            session_duration_p05: 30,
+            session_duration_p25: 30,
+            session_duration_p50: 30,
+            session_duration_p75: 30,
+            session_duration_p90: 30,
+            session_duration_p95: 35,
+            session_duration_p99: 40,
+            session_duration_p99_5: 50,
+            session_duration_p100: 100,
+            qty_events_p05: 3,
+            qty_events_p25: 3,
+            qty_events_p50: 3,
+            qty_events_p75: 3,
+            qty_events_p90: 3,
+            qty_events_p95: 3,
+            qty_events_p99: 3,
+            qty_events_p99_5: 100,
+            qty_events_p100: 100,
+        };
+        let (item_to_top_sessions_ordered, item_to_idf_score, _session_to_items_sorted, item_to_product_attributes) =
+            prepare_hashmap(
+                &historical_sessions,
+                &historical_sessions_max_time_stamp,
+                m_most_recent_sessions,
+                training_data_stats.qty_events_p99_5 as usize,
+                1.0,
+            );
+        VMISIndex {
+            item_to_top_sessions_ordered,
+            session_to_max_time_stamp: historical_sessions_max_time_stamp,
+            item_to_idf_score,
+            session_to_qty_unique_items: count_unique_items(&historical_sessions),
+            session_to_items_sorted: historical_sessions,
+            training_data_stats,
+            item_to_product_attributes,
+            neighbor_similarity: NeighborSimilarity::DotProduct,
         }
     }
 
@@ -306,9 +459,11 @@ impl VMISIndex {
             item_to_top_sessions_ordered,
             session_to_max_time_stamp,
             item_to_idf_score,
+            session_to_qty_unique_items: count_unique_items(&session_to_items_sorted),
             session_to_items_sorted,
             training_data_stats,
             item_to_product_attributes,
+            neighbor_similarity: NeighborSimilarity::DotProduct,
         }
     }
 }
@@ -330,7 +485,10 @@ impl SimilarityComputationNew for VMISIndex {
     ) -> BinaryHeap<SessionScore> {
         // We use a d-ary heap for the (timestamp, session_id) tuple, a hashmap for the (session_id, score) tuples, and a hashmap for the unique items in the evolving session
         let mut heap_timestamps = OctonaryHeap::<SessionTime>::with_capacity(m);
-        let mut session_similarities = HashMap::with_capacity(m);
+        // The decayed dot product, the amount of shared items and the position of the last shared
+        // item per candidate neighbor session. A session is listed once per click on an item, the
+        // position keeps repeated clicks from counting as more shared items.
+        let mut session_similarities: HashMap<u32, (f64, usize, usize)> = HashMap::with_capacity(m);
         let len_evolving_session = evolving_session.len();
         let mut unique = evolving_session.iter().clone().collect_vec();
         unique.sort_unstable();
@@ -353,12 +511,18 @@ impl SimilarityComputationNew for VMISIndex {
                         // Loop over all similar sessions.
                         'session_loop: for session_id in similar_sessions {
                             match session_similarities.get_mut(session_id) {
-                                Some(similarity) => *similarity += decay_factor,
+                                Some((similarity, qty_shared, last_shared_pos)) => {
+                                    *similarity += decay_factor;
+                                    if *last_shared_pos != pos {
+                                        *qty_shared += 1;
+                                        *last_shared_pos = pos;
+                                    }
+                                }
                                 None => {
                                     let session_time_stamp =
                                         self.session_to_max_time_stamp[*session_id as usize];
                                     if session_similarities.len() < m {
-                                        session_similarities.insert(*session_id, decay_factor);
+                                        session_similarities.insert(*session_id, (decay_factor, 1, pos));
                                         heap_timestamps.push(SessionTime::new(
                                             *session_id,
                                             session_time_stamp,
@@ -373,7 +537,7 @@ impl SimilarityComputationNew for VMISIndex {
                                                 .remove_entry(&bottom.session_id);
                                             // Set new minimum timestamp
                                             session_similarities
-                                                .insert(*session_id, decay_factor);
+                                                .insert(*session_id, (decay_factor, 1, pos));
                                             *bottom = SessionTime::new(
                                                 *session_id,
                                                 session_time_stamp,
@@ -390,26 +554,38 @@ impl SimilarityComputationNew for VMISIndex {
             }
         }
 
-        // Return top-k
-        let mut closest_neighbors: BinaryHeap<SessionScore> = BinaryHeap::with_capacity(k);
-        for (session_id, score) in session_similarities.iter() {
-            if closest_neighbors.len() < k {
-                let scored_session = SessionScore::new(*session_id, *score);
-                closest_neighbors.push(scored_session);
-            } else {
-                let mut bottom = closest_neighbors.peek_mut().unwrap();
-                if score > &bottom.score {
-                    let scored_session = SessionScore::new(*session_id, *score);
-                    *bottom = scored_session;
-                } else if (score - bottom.score).abs() < f64::EPSILON
-                    && (self.session_to_max_time_stamp[*session_id as usize]
-                        > self.session_to_max_time_stamp[bottom.id as usize])
-                {
-                    let scored_session = SessionScore::new(*session_id, *score);
-                    *bottom = scored_session;
-                }
-            }
+        // Return top-k, equal scores prefer the most recent session, then the lowest session id.
+        // This is a total order, so the neighbors do not depend on the hash map iteration order.
+        let qty_evolving_items = unique.len();
+        let mut scored_sessions: Vec<(u32, f64)> = session_similarities
+            .into_iter()
+            .map(|(session_id, (score, qty_shared, _last_shared_pos))| {
+                let qty_neighbor_items = self.session_to_qty_unique_items[session_id as usize] as usize;
+                let score = self
+                    .neighbor_similarity
+                    .normalize(score, qty_shared, qty_evolving_items, qty_neighbor_items);
+                (session_id, score)
+            })
+            .collect();
+        let session_to_max_time_stamp = &self.session_to_max_time_stamp;
+        let closer = |(session_a, score_a): &(u32, f64), (session_b, score_b): &(u32, f64)| {
+            score_b
+                .partial_cmp(score_a)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| {
+                    session_to_max_time_stamp[*session_b as usize]
+                        .cmp(&session_to_max_time_stamp[*session_a as usize])
+                })
+                .then_with(|| session_a.cmp(session_b))
+        };
+        if scored_sessions.len() > k && k > 0 {
+            scored_sessions.select_nth_unstable_by(k - 1, closer);
         }
+        scored_sessions.truncate(k);
+        let closest_neighbors: BinaryHeap<SessionScore> = scored_sessions
+            .into_iter()
+            .map(|(session_id, score)| SessionScore::new(session_id, score))
+            .collect();
         // Closest neigbours contain unique session_ids and corresponding top-k similarity scores
         closest_neighbors
     }
@@ -528,6 +704,13 @@ pub(crate) fn prepare_hashmap(
 }
 
 
+pub(crate) fn count_unique_items(sessions: &[Vec<u64>]) -> Vec<u32> {
+    sessions
+        .iter()
+        .map(|session_items| session_items.iter().unique().count() as u32)
+        .collect()
+}
+
 // Custom binary search because this is stable unlike the rust default (i.e. this always returns right-most index in case of duplicate entries instead of a random match)
 fn binary_search_right(array: &[u64], key: u64) -> Result<usize, usize> {
     let mut top: usize = array.len();
@@ -602,6 +785,7 @@ pub fn read_from_file(
     let mut item_id: Vec<usize> = Vec::with_capacity(100_000_000);
     let mut time: Vec<usize> = Vec::with_capacity(100_000_000);
 
+    let mut qty_unparsable_rows = 0;
     reader.deserialize().for_each(|result| {
         if result.is_ok() {
             let raw: (usize, usize, f64) = result.unwrap();
@@ -612,9 +796,13 @@ pub fn read_from_file(
             item_id.push(a_item_id);
             time.push(a_time);
         } else {
-            eprintln!("Unable to parse input!");
+            qty_unparsable_rows += 1;
         }
     });
+    if qty_unparsable_rows > 0 {
+        // Use the preprocess binary to clean the training data and see what is removed.
+        eprintln!("Unable to parse input! Skipped {} rows of {}", qty_unparsable_rows, path);
+    }
 
     // Sort by session id - the data is unsorted
     let mut session_id_indices: Vec<usize> = (0..session_id.len()).into_iter().collect();
@@ -750,3 +938,62 @@ pub fn read_from_file(
         training_data_stats,
     ))
 }
+
+#[cfg(test)]
+mod vmis_index_test {
+    use super::*;
+
+    fn closest_neighbor(neighbor_similarity: NeighborSimilarity) -> (u32, f64) {
+        // the short session shares items 1 and 2, the long session shares items 1, 2 and 3.
+        let historical_sessions = vec![vec![1, 2], (1..=10).collect()];
+        let index = VMISIndex::new_from_sessions(historical_sessions, vec![10, 10], 500)
+            .with_neighbor_similarity(neighbor_similarity);
+        let closest = index.find_neighbors(&[1, 2, 3], 1, 500).into_sorted_vec();
+        (closest[0].id, closest[0].score)
+    }
+
+    #[test]
+    fn should_normalize_neighbor_similarities() {
+        // decayed dot products: 2/3 + 1/3 for the short session, 1 + 2/3 + 1/3 for the long session
+        let (session_id, score) = closest_neighbor(NeighborSimilarity::DotProduct);
+        assert_eq!(1, session_id);
+        assert!((score - 2.0).abs() < 1e-9);
+        let (session_id, score) = closest_neighbor(NeighborSimilarity::Cosine);
+        assert_eq!(0, session_id);
+        assert!((score - 1.0 / 6_f64.sqrt()).abs() < 1e-9);
+        let (session_id, score) = closest_neighbor(NeighborSimilarity::Jaccard);
+        assert_eq!(0, session_id);
+        assert!((score - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_count_repeated_neighbor_items_once() {
+        // the neighbor clicked items 1 and 3 twice, it shares items 1 and 2 out of its unique items 1, 2 and 3.
+        let historical_sessions = vec![vec![1, 1, 2, 3, 3]];
+        let index = VMISIndex::new_from_sessions(historical_sessions, vec![10], 500)
+            .with_neighbor_similarity(NeighborSimilarity::Jaccard);
+        let closest = index.find_neighbors(&[1, 2], 1, 500).into_sorted_vec();
+        // decayed dot product: 2 * 1/2 for item 1 and 2/2 for item 2, the union has 3 items
+        assert!((closest[0].score - 2.0 / 3.0).abs() < 1e-9);
+        let index = index.with_neighbor_similarity(NeighborSimilarity::Cosine);
+        let closest = index.find_neighbors(&[1, 2], 1, 500).into_sorted_vec();
+        assert!((closest[0].score - 2.0 / 6_f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_parse_neighbor_similarity_names() {
+        for name in ["dot_product", "cosine", "jaccard"] {
+            let similarity: NeighborSimilarity = name.parse().unwrap();
+            assert_eq!(name, similarity.to_string());
+        }
+        assert_eq!(
+            vec![NeighborSimilarity::DotProduct, NeighborSimilarity::Jaccard],
+            parse_neighbor_similarities("dot_product, jaccard").unwrap()
+        );
+        assert!(parse_neighbor_similarities("dot_product,dice").is_err());
+        assert_eq!(
+            "Unknown neighbor similarity: dice, expected one of dot_product, cosine, jaccard",
+            "dice".parse::<NeighborSimilarity>().unwrap_err()
+        );
+    }
+}
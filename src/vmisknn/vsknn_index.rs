@@ -8,14 +8,21 @@ use hashbrown::hash_map::DefaultHashBuilder as RandomState;
 use itertools::Itertools;
 
 use crate::io::{ItemId, Time, TrainingSessionId};
-use crate::vmisknn::{SessionScore, SessionTime};
+use crate::recommender::{RecommendOptions, Recommender};
+use crate::vmisknn::{
+    dedup_consecutive_items, linear_score, passes_business_rules, ItemScore, SessionScore, SessionTime,
+};
 use crate::vmisknn::similarity_hashed::{idf, SimilarityComputationHash};
+use crate::vmisknn::vmis_index::ProductAttributes;
 
+/// The reference V-SkNN implementation with hash-based indexes. Slower than `VMISIndex`, but
+/// straightforward enough to validate the accuracy of VMIS-kNN against.
 pub struct VSkNNIndex {
     session_index: HashMap<TrainingSessionId, HashSet<ItemId>>,
     session_max_order: HashMap<TrainingSessionId, Time>,
     item_index: HashMap<ItemId, HashSet<TrainingSessionId>>,
     item_idfs: HashMap<ItemId, f64>,
+    item_to_product_attributes: HashMap<ItemId, ProductAttributes>,
 }
 
 impl SimilarityComputationHash for VSkNNIndex {
@@ -27,6 +34,10 @@ impl SimilarityComputationHash for VSkNNIndex {
         self.item_idfs[item]
     }
 
+    fn find_attributes(&self, item_id: &ItemId) -> Option<&ProductAttributes> {
+        self.item_to_product_attributes.get(item_id)
+    }
+
     fn find_neighbors(
         &self,
         evolving_session: &[ItemId],
@@ -36,10 +47,15 @@ impl SimilarityComputationHash for VSkNNIndex {
         let num_items_in_evolving_session = evolving_session.len();
 
         let mut most_recent_neighbors: BinaryHeap<SessionScore> = BinaryHeap::with_capacity(m);
+        // A training session that shares several items with the evolving session is a single neighbor.
+        let mut candidate_sessions: HashSet<TrainingSessionId> = HashSet::new();
 
         for session_item in evolving_session.iter() {
             if let Some(sessions) = self.sessions_for_item(session_item) {
                 for session in sessions {
+                    if !candidate_sessions.insert(*session) {
+                        continue;
+                    }
                     let max_order = self.max_order_for_session(session);
 
                     let session_with_age = SessionScore::new(*session, max_order as f64);
@@ -48,7 +64,7 @@ impl SimilarityComputationHash for VSkNNIndex {
                         most_recent_neighbors.push(session_with_age);
                     } else {
                         let mut top = most_recent_neighbors.peek_mut().unwrap();
-                        if session_with_age.score > top.score {
+                        if session_with_age < *top {
                             *top = session_with_age;
                         }
                     }
@@ -85,7 +101,7 @@ impl SimilarityComputationHash for VSkNNIndex {
                     closest_neighbors.push(scored_session);
                 } else {
                     let mut bottom = closest_neighbors.peek_mut().unwrap();
-                    if scored_session.score > bottom.score {
+                    if scored_session < *bottom {
                         *bottom = scored_session;
                     }
                 }
@@ -96,11 +112,94 @@ impl SimilarityComputationHash for VSkNNIndex {
     }
 }
 
+impl Recommender for VSkNNIndex {
+    fn recommend(&self, evolving_session: &[ItemId], options: &RecommendOptions) -> Vec<ItemScore> {
+        let deduplicated;
+        let evolving_session = if options.dedup_consecutive_items {
+            deduplicated = dedup_consecutive_items(evolving_session);
+            &deduplicated[..]
+        } else {
+            evolving_session
+        };
+        let most_recent_item = match evolving_session.last() {
+            Some(most_recent_item) => *most_recent_item,
+            None => return Vec::new(),
+        };
+        let how_many = options.num_items_to_recommend;
+        if how_many == 0 {
+            return Vec::new();
+        }
+        let neighbors = self.find_neighbors(
+            evolving_session,
+            options.neighborhood_size_k,
+            options.m_most_recent_sessions,
+        );
+
+        let mut item_scores: HashMap<ItemId, f64> = HashMap::with_capacity(1000);
+
+        // Sum the neighbor contributions in a fixed order, floating point sums depend on the order.
+        for scored_session in neighbors.into_sorted_vec().into_iter() {
+            let training_item_ids = self.items_for_session(&scored_session.id);
+
+            let first_match_index = evolving_session
+                .iter()
+                .rev()
+                .position(|item_id| training_item_ids.contains(item_id));
+            let session_weight = match first_match_index {
+                Some(first_match_index) => linear_score(first_match_index + 1),
+                None => continue,
+            };
+
+            for item_id in training_item_ids.iter() {
+                let item_idf = self.idf(item_id);
+                let item_weight = if item_idf > 0.0 { item_idf } else { 1.0 };
+                *item_scores.entry(*item_id).or_insert(0.0) +=
+                    session_weight * item_weight * scored_session.score;
+            }
+        }
+
+        item_scores.remove(&most_recent_item);
+
+        let current_item_attribs = self.find_attributes(&most_recent_item);
+        let mut top_items: BinaryHeap<ItemScore> = BinaryHeap::with_capacity(how_many);
+        for (item_id, score) in item_scores.into_iter() {
+            if options.enable_business_logic
+                && !passes_business_rules(current_item_attribs, self.find_attributes(&item_id))
+            {
+                continue;
+            }
+            let scored_item = ItemScore::new(item_id, score);
+            if top_items.len() < how_many {
+                top_items.push(scored_item);
+            } else {
+                let mut bottom = top_items.peek_mut().unwrap();
+                if scored_item < *bottom {
+                    *bottom = scored_item;
+                }
+            }
+        }
+        top_items.into_sorted_vec()
+    }
+}
+
 impl VSkNNIndex {
+    /// Creates an index from a csv file with training data, see `io::read_training_data`.
+    pub fn new_from_csv(path_to_training: &str, sample_size_m: usize, idf_weighting: f64) -> Self {
+        let interactions = crate::io::read_training_data(path_to_training);
+        VSkNNIndex::new(interactions, sample_size_m, usize::MAX, idf_weighting)
+    }
+
+    /// # Arguments
+    ///
+    /// * `interactions` - the training interactions, the time only matters to pick the most recent sessions.
+    /// * `sample_size_m` - the amount of most recent sessions retained per item.
+    /// * `max_qty_session_items` - items with more retained sessions than this are ignored.
+    /// * `idf_weighting` - multiplies the idf score of the items, as in `VMISIndex`.
     pub fn new(
         interactions: Vec<(TrainingSessionId, ItemId, Time)>,
         sample_size_m: usize,
         max_qty_session_items: usize,
+        idf_weighting: f64,
     ) -> Self {
         // start only need to retain sample_size_m sessions per item
         let valid_session_ids: HashSet<u32> = interactions
@@ -172,17 +271,30 @@ impl VSkNNIndex {
         let item_idfs: HashMap<u64, f64> = historical_item_index
             .iter()
             .map(|(item, session_ids)| {
-                let item_idf = idf(num_historical_sessions, session_ids.len());
+                let item_idf = idf(num_historical_sessions, session_ids.len()) * idf_weighting;
 
                 (*item, item_idf)
             })
             .collect();
 
+        // Product attributes are not part of the training data yet, identical to `VMISIndex`.
+        let item_to_product_attributes: HashMap<ItemId, ProductAttributes> = historical_item_index
+            .keys()
+            .map(|item| {
+                let attributes = ProductAttributes {
+                    is_adult: false,
+                    is_for_sale: true,
+                };
+                (*item, attributes)
+            })
+            .collect();
+
         VSkNNIndex {
             session_index: historical_session_index,
             session_max_order: historical_session_max_order,
             item_index: historical_item_index,
             item_idfs,
+            item_to_product_attributes,
         }
     }
 
@@ -194,3 +306,63 @@ impl VSkNNIndex {
         self.session_max_order[session]
     }
 }
+
+#[cfg(test)]
+mod vsknn_index_test {
+    use super::*;
+
+    #[test]
+    fn should_train_and_recommend() {
+        let interactions = vec![
+            (1, 920006, 1),
+            (1, 920005, 2),
+            (1, 920004, 3),
+            (2, 920005, 1),
+            (2, 920004, 2),
+            (2, 920003, 3),
+            (2, 920002, 4),
+        ];
+        let undertest = VSkNNIndex::new(interactions, 500, 100, 1.0);
+        let options = RecommendOptions {
+            neighborhood_size_k: 500,
+            m_most_recent_sessions: 500,
+            num_items_to_recommend: 20,
+            enable_business_logic: false,
+            dedup_consecutive_items: false,
+        };
+
+        let recommendations = undertest.recommend(&[920005], &options);
+
+        // we expect the four other item_ids to be recommended, 920004 occurs in both neighbors
+        assert_eq!(4, recommendations.len());
+        assert_eq!(920004, recommendations[0].id);
+    }
+
+    #[test]
+    fn should_count_each_neighbor_session_once() {
+        let interactions = vec![(1, 10, 1), (1, 11, 2), (1, 12, 3), (2, 13, 1)];
+        let undertest = VSkNNIndex::new(interactions, 500, 100, 1.0);
+
+        let neighbors = undertest.find_neighbors(&[10, 11], 500, 500);
+
+        assert_eq!(1, neighbors.len());
+        // decayed dot product of both matching items: 1/2 + 2/2
+        assert_eq!(1.5, neighbors.peek().unwrap().score);
+    }
+
+    #[test]
+    fn should_recommend_nothing_for_unknown_items() {
+        let interactions = vec![(1, 10, 1), (1, 11, 2)];
+        let undertest = VSkNNIndex::new(interactions, 500, 100, 1.0);
+        let options = RecommendOptions {
+            neighborhood_size_k: 500,
+            m_most_recent_sessions: 500,
+            num_items_to_recommend: 20,
+            enable_business_logic: true,
+            dedup_consecutive_items: false,
+        };
+
+        assert!(undertest.recommend(&[999], &options).is_empty());
+        assert!(undertest.recommend(&[], &options).is_empty());
+    }
+}